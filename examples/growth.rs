@@ -18,7 +18,7 @@ fn main() {
 	let mut f_n = GaugeVector::new(
         "functions",
         0.0,
-        &["log2(n)", "nlog2(n)", "n^2", "n^3", "n^4", "2^n", "10^n"],
+        &["log2_n", "n_log2_n", "n_pow_2", "n_pow_3", "n_pow_4", "2_pow_n", "10_pow_n"],
         "Growth of various functions", "").unwrap();
 
     let client = Client::new("growth").unwrap();
@@ -29,13 +29,13 @@ fn main() {
 
         let val = n.val() as f64;
 
-        f_n.set("log2(n)", val.log2()).unwrap().unwrap();
-        f_n.set("nlog2(n)", val*val.log2()).unwrap().unwrap();
-        f_n.set("n^2", val.powi(2)).unwrap().unwrap();
-        f_n.set("n^3", val.powi(3)).unwrap().unwrap();
-        f_n.set("n^4", val.powi(4)).unwrap().unwrap();
-        f_n.set("2^n", val.exp2()).unwrap().unwrap();
-        f_n.set("10^n", 10_f64.powf(val)).unwrap().unwrap();
+        f_n.set("log2_n", val.log2()).unwrap().unwrap();
+        f_n.set("n_log2_n", val*val.log2()).unwrap().unwrap();
+        f_n.set("n_pow_2", val.powi(2)).unwrap().unwrap();
+        f_n.set("n_pow_3", val.powi(3)).unwrap().unwrap();
+        f_n.set("n_pow_4", val.powi(4)).unwrap().unwrap();
+        f_n.set("2_pow_n", val.exp2()).unwrap().unwrap();
+        f_n.set("10_pow_n", 10_f64.powf(val)).unwrap().unwrap();
 
         n.up().unwrap();
 
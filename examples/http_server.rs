@@ -2,7 +2,6 @@ extern crate hornet;
 extern crate hyper;
 extern crate futures;
 
-use std::sync::{Mutex, Arc};
 use hornet::client::Client;
 use hornet::client::metric::*;
 use futures::future::FutureResult;
@@ -20,7 +19,7 @@ use hyper::server::{Http, Service, Request, Response};
 static URL: &'static str = "127.0.0.1:8000";
 
 struct HTTPCounterService {
-    arc: Arc<Mutex<Counter>>
+    counter: SharedMetric<Counter>
 }
 
 impl Service for HTTPCounterService {
@@ -33,7 +32,7 @@ impl Service for HTTPCounterService {
         futures::future::ok(match (req.method(), req.path()) {
             (&Get, "/") => {
 
-                let mut counter = self.arc.lock().unwrap();
+                let mut counter = self.counter.lock();
 
                 /* increase the counter value by one */
                 counter.up().unwrap();
@@ -58,33 +57,32 @@ fn main() {
 
 	/* create a counter metric */
 
-	let mut counter = Counter::new(
+	let counter = Counter::new(
         "get",
         0, // initial value
         "GET request count", // short description
         &format!("Count of GET requests on http://{}/", URL) // long description
     ).unwrap();
 
+    /* 
+        since the counter could be updated concurrently, wrap it
+        in a shared metric, which holds it in a mutex behind an
+        atomic reference counting pointer
+    */
+
+    let mut counter = SharedMetric::new(counter);
+
     /* export it to an mmv */
 
     let client = Client::new("localhost.http").unwrap();
     client.export(&mut [&mut counter]).unwrap();
 
-    /* 
-        since the counter could be updated concurrently, wrap it
-        in a mutex. to have shared ownership of the mutex itself,
-        wrap it in an atomic reference counting pointer
-    */
-     
-    let mutex = Mutex::new(counter);
-    let arc = Arc::new(mutex);
-
     /* create and run the server */
 
     let addr = URL.parse().unwrap();
     let server = Http::new().bind(&addr, move || {
         Ok(HTTPCounterService {
-            arc: arc.clone()
+            counter: counter.clone()
         })
     }).unwrap();
 
@@ -5,9 +5,23 @@ use std::env;
 use std::path::Path;
 
 fn main() {
-    let path_arg = env::args().nth(1)
-        .expect("Specify path to mmv file");
+    let mut json = false;
+    let mut path_arg = None;
+    for arg in env::args().skip(1) {
+        if arg == "--json" {
+            json = true;
+        } else {
+            path_arg = Some(arg);
+        }
+    }
+
+    let path_arg = path_arg
+        .expect("Usage: mmvdump [--json] <path to mmv file>");
     let mmv_path = Path::new(&path_arg);
 
-    print!("{}", mmv::dump(&mmv_path).unwrap());
+    if json {
+        println!("{}", mmv::to_json(&mmv::dump(&mmv_path).unwrap()));
+    } else {
+        print!("{}", mmv::dump_to_string(&mmv_path).unwrap());
+    }
 }
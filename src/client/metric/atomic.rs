@@ -0,0 +1,178 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use super::*;
+
+/// A metric whose value is updated with atomic operations directly on
+/// its slot in the MMV, so it can be updated from many threads without
+/// a `Mutex`
+///
+/// Once exported, an `AtomicMetric` can be shared behind an `Arc` and
+/// incremented concurrently; increments wrap around on overflow. Before
+/// it's exported, the value lives in a private mapping of its own.
+///
+/// Exporting needs exclusive access, like for any other metric, and
+/// `on_change` callbacks aren't supported.
+pub struct AtomicMetric<T> {
+    metric: Metric<T>
+}
+
+impl AtomicMetric<u64> {
+    /// Creates a new atomic `u64` metric
+    ///
+    /// The result is an error in the same cases as `Metric::new`, or if
+    /// the private mapping for the value can't be created.
+    pub fn new(name: &str, init_val: u64, sem: Semantics, unit: Unit,
+        shorthelp: &str, longhelp: &str) -> Result<Self, String> {
+
+        let mut metric = Metric::new(name, init_val, sem, unit, shorthelp, longhelp)?;
+        metric.mmap_view = Mmap::anonymous(NUMERIC_VALUE_SIZE, Protection::ReadWrite)
+            .map_err(|err| err.to_string())?
+            .into_view_sync();
+
+        let atomic = AtomicMetric { metric: metric };
+        atomic.slot().store(init_val.to_le(), Ordering::SeqCst);
+        Ok(atomic)
+    }
+
+    // the value blocks of an MMV are 8-byte aligned, as every block
+    // length is a multiple of 8, and the private mapping is page aligned;
+    // the MMV's is checked before the metric is pointed to it
+    fn slot(&self) -> &AtomicU64 {
+        unsafe { &*(self.metric.mmap_view.ptr() as *const AtomicU64) }
+    }
+
+    /// Returns the current value of the metric
+    pub fn val(&self) -> u64 {
+        u64::from_le(self.slot().load(Ordering::SeqCst))
+    }
+
+    /// Sets the value of the metric
+    pub fn set_val(&self, new_val: u64) {
+        self.slot().store(new_val.to_le(), Ordering::SeqCst);
+    }
+
+    /// Increments the metric by the given value, returning the previous
+    /// value
+    #[cfg(target_endian = "little")]
+    pub fn inc(&self, increment: u64) -> u64 {
+        self.slot().fetch_add(increment, Ordering::SeqCst)
+    }
+
+    /// Increments the metric by the given value, returning the previous
+    /// value
+    #[cfg(target_endian = "big")]
+    pub fn inc(&self, increment: u64) -> u64 {
+        // the MMV is little endian, so the addition can't be done by
+        // the atomic itself
+        let slot = self.slot();
+        let mut current = slot.load(Ordering::SeqCst);
+        loop {
+            let new = u64::from_le(current).wrapping_add(increment).to_le();
+            match slot.compare_exchange_weak(current, new, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => return u64::from_le(current),
+                Err(actual) => current = actual
+            }
+        }
+    }
+
+    /// Increments the metric by `+1`, returning the previous value
+    pub fn up(&self) -> u64 {
+        self.inc(1)
+    }
+}
+
+impl fmt::Debug for AtomicMetric<u64> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AtomicMetric")
+            .field("name", &self.metric.name)
+            .field("val", &self.val())
+            .finish()
+    }
+}
+
+impl MMVWriter for AtomicMetric<u64> {
+    private_impl!{}
+
+    fn write(&mut self, ws: &mut MMVWriterState, c: &mut Cursor<&mut [u8]>, mmv_ver: Version) -> io::Result<()> {
+        // the value is written at the start of the next value block;
+        // checking it upfront keeps the current mapping if it's unusable
+        if ws.remap_metrics {
+            if let Some(ref view) = ws.mmap_view {
+                let value_off = (ws.value_sec_off + ws.value_blk_idx*VALUE_BLOCK_LEN) as usize;
+                if view.len() < value_off + NUMERIC_VALUE_SIZE
+                    || (view.ptr() as usize + value_off) % mem::align_of::<AtomicU64>() != 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("value of atomic metric {} isn't aligned", self.metric.name)
+                    ));
+                }
+            }
+        }
+
+        self.metric.val = self.val();
+        self.metric.write(ws, c, mmv_ver)
+    }
+
+    fn register(&self, ws: &mut MMVWriterState, mmv_ver: Version) -> io::Result<()> {
+        self.metric.register(ws, mmv_ver)
+    }
+
+    fn has_mmv2_string(&self) -> bool {
+        self.metric.has_mmv2_string()
+    }
+}
+
+#[test]
+pub fn test() {
+    use std::thread;
+    use super::super::Client;
+
+    let mut counter = AtomicMetric::new(
+        "atomic_counter", 1, Semantics::Counter, Unit::new(), "", ""
+    ).unwrap();
+    assert_eq!(counter.up(), 1);
+    assert_eq!(counter.val(), 2);
+
+    // increments before exporting are exported
+    Client::new("atomic_metric_test").unwrap()
+        .export(&mut [&mut counter]).unwrap();
+    assert_eq!(u64::read(&mut unsafe { counter.metric.mmap_view.as_slice() }).unwrap(), 2);
+
+    let counter = Arc::new(counter);
+    let handles: Vec<_> = (0..4).map(|_| {
+        let counter = counter.clone();
+        thread::spawn(move || {
+            for _ in 0..10000 {
+                counter.up();
+            }
+        })
+    }).collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let mapped = u64::read(&mut unsafe { counter.metric.mmap_view.as_slice() }).unwrap();
+    assert_eq!(mapped, 40002);
+    assert_eq!(counter.val(), 40002);
+
+    counter.set_val(u64::max_value());
+    assert_eq!(counter.inc(2), u64::max_value());
+    assert_eq!(counter.val(), 1);
+}
+
+#[test]
+pub fn test_unaligned() {
+    let mut counter = AtomicMetric::new(
+        "unaligned_counter", 7, Semantics::Counter, Unit::new(), "", ""
+    ).unwrap();
+    let mapped = counter.metric.mmap_view.ptr();
+
+    let mut view = Mmap::anonymous(64, Protection::ReadWrite).unwrap().into_view_sync();
+    view.restrict(4, 32).unwrap();
+    let mut ws = MMVWriterState::new();
+    ws.mmap_view = Some(view);
+
+    let mut buf = [0u8; 32];
+    assert!(counter.write(&mut ws, &mut Cursor::new(&mut buf[..]), Version::V1).is_err());
+    assert_eq!(counter.metric.mmap_view.ptr(), mapped);
+    assert_eq!(counter.up(), 7);
+}
@@ -0,0 +1,109 @@
+use super::*;
+
+/// Buffers new values for the instances of an `InstanceMetric`, and
+/// writes them all at once on `commit` (or when dropped)
+///
+/// Values set through the batch aren't visible, neither through the
+/// metric nor in the MMV, until they're committed. Committing writes
+/// them in the order their value blocks are laid out in the MMV, in a
+/// single pass over the mapped region.
+pub struct BatchUpdate<'a, T: 'a + MetricType + Clone> {
+    im: &'a mut InstanceMetric<T>,
+    pending: HashMap<String, T>
+}
+
+impl<'a, T: MetricType + Clone> BatchUpdate<'a, T> {
+    pub(super) fn new(im: &'a mut InstanceMetric<T>) -> Self {
+        BatchUpdate {
+            im: im,
+            pending: HashMap::new()
+        }
+    }
+
+    /// Buffers a new value for the given instance, replacing any value
+    /// buffered for it before. If the instance isn't found, returns
+    /// `None`.
+    pub fn set_val(&mut self, instance: &str, new_val: T) -> Option<()> {
+        if !self.im.vals.contains_key(instance) {
+            return None;
+        }
+        self.pending.insert(instance.to_owned(), new_val);
+        Some(())
+    }
+
+    /// Returns the number of instances with a buffered value
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Returns whether no value is buffered
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Writes all the buffered values
+    ///
+    /// If writing a value fails (e.g, a `String` value exceeding 255
+    /// bytes), the values after it in MMV order are discarded.
+    pub fn commit(mut self) -> io::Result<()> {
+        self.flush()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let vals = &mut self.im.vals;
+
+        let mut pending: Vec<(String, T)> = self.pending.drain().collect();
+        pending.sort_by_key(|&(ref instance, _)|
+            unsafe { vals[instance].mmap_view.as_slice().as_ptr() as usize }
+        );
+
+        for (instance, new_val) in pending {
+            if let Some(i) = vals.get_mut(&instance) {
+                new_val.write(unsafe { &mut i.mmap_view.as_mut_slice() })?;
+                i.val = new_val;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, T: MetricType + Clone> Drop for BatchUpdate<'a, T> {
+    fn drop(&mut self) {
+        // errors can only be observed by calling `commit`
+        self.flush().ok();
+    }
+}
+
+#[test]
+pub fn test() {
+    use super::super::Client;
+
+    let instances: Vec<String> = (0..1000).map(|n| format!("inst{}", n)).collect();
+    let instance_refs: Vec<&str> = instances.iter().map(|s| s.as_str()).collect();
+    let indom = Indom::new(&instance_refs, "", "").unwrap();
+    let mut im = InstanceMetric::new(
+        &indom, "batched", 0u64, Semantics::Instant, Unit::new(), "", ""
+    ).unwrap();
+
+    Client::new("batch_update_test").unwrap()
+        .export(&mut [&mut im]).unwrap();
+
+    {
+        let mut batch = im.batch();
+        for (n, instance) in instances.iter().enumerate() {
+            assert_eq!(batch.set_val(instance, n as u64), Some(()));
+        }
+        assert_eq!(batch.set_val("missing", 1), None);
+        assert_eq!(batch.len(), instances.len());
+        batch.commit().unwrap();
+    }
+
+    for (n, instance) in instances.iter().enumerate() {
+        assert_eq!(im.val(instance), Some(&(n as u64)));
+        assert_eq!(im.read_mapped(instance).unwrap().unwrap(), n as u64);
+    }
+
+    // dropping the batch commits it too
+    im.batch().set_val("inst7", 77).unwrap();
+    assert_eq!(im.val("inst7"), Some(&77));
+}
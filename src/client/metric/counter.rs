@@ -3,28 +3,65 @@ use super::*;
 /// A counter metric for a strictly increasing integer value, in
 /// possibly varying increments
 ///
-/// Internally uses a `Metric<u64>` with `Semantics::Counter` and
-/// `Count::One` scale, and `1` count dimension
+/// Internally uses a `Metric<u64>` with `Semantics::Counter` and, unless
+/// created with `with_unit`, `Count::One` scale and `1` count dimension
+#[derive(Debug)]
 pub struct Counter {
     metric: Metric<u64>,
-    init_val: u64
+    init_val: u64,
+    last_reported: u64
 }
 
 impl Counter {
     /// Creates a new counter metric with given initial value
     pub fn new(name: &str, init_val: u64, shorthelp_text: &str, longhelp_text: &str) -> Result<Self, String> {
+        Counter::with_unit(
+            name,
+            init_val,
+            Unit::new().count(Count::One, 1)?,
+            shorthelp_text,
+            longhelp_text
+        )
+    }
+
+    /// Creates a new counter metric with given initial value and unit,
+    /// e.g, a space unit for a counter of bytes
+    pub fn with_unit(name: &str, init_val: u64, unit: Unit,
+        shorthelp_text: &str, longhelp_text: &str) -> Result<Self, String> {
         let metric = Metric::new(
             name,
             init_val,
             Semantics::Counter,
-            Unit::new().count(Count::One, 1)?,
+            unit,
             shorthelp_text,
             longhelp_text
         )?;
 
         Ok(Counter {
             metric: metric,
-            init_val: init_val
+            init_val: init_val,
+            last_reported: init_val
+        })
+    }
+
+    /// Wraps an existing metric, e.g, one created with custom
+    /// help texts and unit
+    ///
+    /// The result is an error unless the metric has `Semantics::Counter`.
+    /// `reset` resets the counter to the metric's initial value, and
+    /// `take_delta` counts from the metric's current value.
+    pub fn from_metric(metric: Metric<u64>) -> Result<Self, String> {
+        match metric.sem {
+            Semantics::Counter => {},
+            _ => return Err(format!("metric {} doesn't have counter semantics", metric.name))
+        }
+
+        let init_val = metric.init_val;
+        let last_reported = *metric.val();
+        Ok(Counter {
+            metric: metric,
+            init_val: init_val,
+            last_reported: last_reported
         })
     }
 
@@ -44,9 +81,24 @@ impl Counter {
         self.inc(1)
     }
 
+    /// Returns the increment since the last call to `take_delta` (or
+    /// since creating or resetting the counter), and marks the current
+    /// value as reported
+    ///
+    /// The marker is only kept in memory and isn't written to the MMV.
+    /// If the value dropped below the marker, e.g, after it was reset
+    /// through the wrapped metric, the increment is `0`.
+    pub fn take_delta(&mut self) -> u64 {
+        let val = *self.metric.val();
+        let delta = val.saturating_sub(self.last_reported);
+        self.last_reported = val;
+        delta
+    }
+
     /// Resets the counter to the initial value that was passed when
     /// creating it
     pub fn reset(&mut self) -> io::Result<()> {
+        self.last_reported = self.init_val;
         self.metric.set_val(self.init_val)
     }
 }
@@ -58,7 +110,7 @@ impl MMVWriter for Counter {
         self.metric.write(ws, c, mmv_ver)
     }
 
-    fn register(&self, ws: &mut MMVWriterState, mmv_ver: Version) {
+    fn register(&self, ws: &mut MMVWriterState, mmv_ver: Version) -> io::Result<()> {
         self.metric.register(ws, mmv_ver)
     }
 
@@ -85,4 +137,61 @@ pub fn test() {
 
     counter.reset().unwrap();
     assert_eq!(counter.val(), 1);
+
+    counter.inc(5).unwrap();
+    assert_eq!(counter.take_delta(), 5);
+    counter.inc(3).unwrap();
+    assert_eq!(counter.take_delta(), 3);
+    assert_eq!(counter.take_delta(), 0);
+    assert_eq!(counter.val(), 9);
+}
+
+#[test]
+pub fn test_with_unit() {
+    use super::super::Client;
+    use super::super::super::mmv::dump;
+
+    let unit = Unit::new().space(Space::Byte, 1).unwrap();
+    let mut counter = Counter::with_unit("byte_counter", 0, unit, "", "").unwrap();
+    counter.inc(512).unwrap();
+
+    let client = Client::new("byte_counter_test").unwrap();
+    client.export(&mut [&mut counter]).unwrap();
+
+    let mmv = dump(client.mmv_path()).unwrap();
+    let (_, metric_blk) = mmv.metric_blks().iter().next().unwrap();
+    assert_eq!(metric_blk.unit(), u32::from(unit));
+    assert_eq!(Unit::from_raw(metric_blk.unit()).describe(), "B");
+    assert_eq!(metric_blk.sem(), Semantics::Counter as u32);
+}
+
+#[test]
+pub fn test_from_metric() {
+    use super::super::Client;
+
+    let unit = Unit::new().space(Space::Byte, 1).unwrap();
+    let mut metric = Metric::new("custom_counter", 2, Semantics::Counter, unit, "", "").unwrap();
+    metric.set_val(5).unwrap();
+    let mut counter = Counter::from_metric(metric).unwrap();
+    assert_eq!(counter.take_delta(), 0);
+
+    Client::new("counter_from_metric_test").unwrap()
+        .export(&mut [&mut counter]).unwrap();
+
+    counter.inc(3).unwrap();
+    counter.up().unwrap();
+    assert_eq!(counter.val(), 9);
+    assert_eq!(counter.metric.read_mapped().unwrap(), 9);
+    assert_eq!(counter.take_delta(), 4);
+
+    counter.metric.set_val(3).unwrap();
+    assert_eq!(counter.take_delta(), 0);
+    counter.up().unwrap();
+    assert_eq!(counter.take_delta(), 1);
+
+    counter.reset().unwrap();
+    assert_eq!(counter.val(), 2);
+
+    let metric = Metric::new("instant_counter", 0, Semantics::Instant, unit, "", "").unwrap();
+    assert!(Counter::from_metric(metric).is_err());
 }
@@ -6,10 +6,12 @@ use super::*;
 ///
 /// Internally uses an `InstanceMetric<u64>` with `Semantics::Counter` and
 /// `Count::One` scale, and `1` count dimension
+#[derive(Debug)]
 pub struct CountVector {
     im: InstanceMetric<u64>,
     indom: Indom,
-    init_vals: HashMap<String, u64>
+    init_vals: HashMap<String, u64>,
+    last_reported: HashMap<String, u64>
 }
 
 impl CountVector {
@@ -31,6 +33,10 @@ impl CountVector {
     }
 
     /// Creates a new count vector with given pairs of an instance and it's initial value
+    ///
+    /// The result is an error if an instance appears in more than one
+    /// pair, if two instances hash to the same instance ID, or if the
+    /// length of `name`, any instance or the help texts exceed 255 bytes.
     pub fn new_with_initvals(name: &str, instances_and_initvals: &[(&str, u64)],
         shorthelp_text: &str, longhelp_text: &str) -> Result<Self, String> {
         
@@ -39,16 +45,16 @@ impl CountVector {
             instances.push(instance);
         }
 
-        let indom_helptext = format!("Instance domain for CounterVector '{}'", name);
+        let indom_helptext = indom_helptext("CounterVector", name);
         let indom = Indom::new(
             &instances,
             &indom_helptext, &indom_helptext
         )?;
         
-        let mut im = InstanceMetric::new(
+        let im = InstanceMetric::new_with_vals(
             &indom,
             name,
-            0,
+            instances_and_initvals,
             Semantics::Counter,
             Unit::new().count(Count::One, 1)?,
             shorthelp_text,
@@ -58,12 +64,12 @@ impl CountVector {
         let mut init_vals = HashMap::new();
         for &(instance, init_val) in instances_and_initvals.iter() {
             init_vals.insert(instance.to_owned(), init_val);
-            im.set_val(instance, init_val).unwrap().unwrap();
         }
 
         Ok(CountVector {
             im: im,
             indom: indom,
+            last_reported: init_vals.clone(),
             init_vals: init_vals
         })
     }
@@ -73,6 +79,15 @@ impl CountVector {
         self.im.val(instance).cloned()
     }
 
+    /// Returns the current count of every instance
+    pub fn values(&self) -> HashMap<String, u64> {
+        self.indom.instances_iter()
+            .filter_map(|instance|
+                self.im.val(instance).map(|val| (instance.to_owned(), *val))
+            )
+            .collect()
+    }
+
     /// Increments the count of the instance by the given value
     ///
     /// The wrapping `Option` is `None` if the instance wasn't found
@@ -103,23 +118,50 @@ impl CountVector {
         self.inc_all(1)
     }
 
+    /// Returns the increment of the instance's count since the last call
+    /// to `take_delta` for it (or since creating the vector or resetting
+    /// the instance), and marks the current count as reported
+    ///
+    /// The marker is only kept in memory and isn't written to the MMV.
+    /// The result is `None` if the instance wasn't found
+    pub fn take_delta(&mut self, instance: &str) -> Option<u64> {
+        let val = self.im.val(instance).cloned()?;
+        let last_reported = self.last_reported.get_mut(instance)?;
+        let delta = val - *last_reported;
+        *last_reported = val;
+        Some(delta)
+    }
+
     /// Resets the count of the instance to it's initial value that
     /// was passed when creating the vector
     ///
     /// The wrapping `Option` is `None` if the instance wasn't found
     pub fn reset(&mut self, instance: &str) -> Option<io::Result<()>> {
-        self.im.set_val(instance, *self.init_vals.get(instance).unwrap())
+        let init_val = *self.init_vals.get(instance)?;
+        self.last_reported.insert(instance.to_owned(), init_val);
+        self.im.set_val(instance, init_val)
     }
 
     /// Resets the count of all instances to it's initial value that
     /// was passed when creating the vector
     pub fn reset_all(&mut self) -> io::Result<()> {
         for (instance, init_val) in self.init_vals.iter() {
+            self.last_reported.insert(instance.to_owned(), *init_val);
             self.im.set_val(instance, *init_val).unwrap()?;
         }
         Ok(())
     }
 
+    /// Sets the short and long help texts of the internally created
+    /// instance domain, replacing the generated ones
+    ///
+    /// The result is an error if the length of `shorthelp` or
+    /// `longhelp` exceeds 255 bytes.
+    pub fn set_indom_help(&mut self, shorthelp: &str, longhelp: &str) -> Result<(), String> {
+        self.im.set_indom_help(shorthelp, longhelp)?;
+        self.indom.set_help(shorthelp, longhelp)
+    }
+
     /// Internally created instance domain
     pub fn indom(&self) -> &Indom { &self.indom }
 }
@@ -131,7 +173,7 @@ impl MMVWriter for CountVector {
         self.im.write(ws, c, mmv_ver)
     }
 
-    fn register(&self, ws: &mut MMVWriterState, mmv_ver: Version) {
+    fn register(&self, ws: &mut MMVWriterState, mmv_ver: Version) -> io::Result<()> {
         self.im.register(ws, mmv_ver)
     }
 
@@ -181,6 +223,21 @@ pub fn test() {
     assert_eq!(cv.val("a").unwrap(), 1);   
     assert_eq!(cv.val("b").unwrap(), 1);
     assert_eq!(cv.val("c").unwrap(), 1);
+
+    cv.inc("a", 5).unwrap().unwrap();
+    assert_eq!(cv.take_delta("a"), Some(5));
+    cv.inc("a", 3).unwrap().unwrap();
+    assert_eq!(cv.take_delta("a"), Some(3));
+    assert_eq!(cv.take_delta("b"), Some(0));
+    assert_eq!(cv.take_delta("d"), None);
+
+    cv.inc("b", 1).unwrap().unwrap();
+    cv.inc("c", 2).unwrap().unwrap();
+    let values = cv.values();
+    assert_eq!(values.len(), 3);
+    assert_eq!(values["a"], 9);
+    assert_eq!(values["b"], 2);
+    assert_eq!(values["c"], 3);
 }
 
 #[test]
@@ -213,3 +270,26 @@ pub fn test_multiple_initvals() {
     assert_eq!(cv.val("b").unwrap(), 2);
     assert_eq!(cv.val("c").unwrap(), 3);
 }
+
+#[test]
+pub fn test_indom_help() {
+    use super::super::Client;
+    use super::super::super::mmv::dump;
+
+    let mut cv = CountVector::new(
+        "count_vector_indom_help", 0, &["get", "post"], "", ""
+    ).unwrap();
+    cv.set_indom_help("HTTP methods", "Methods of handled HTTP requests").unwrap();
+    assert_eq!(cv.indom().shorthelp(), "HTTP methods");
+    assert!(cv.set_indom_help(&"x".repeat(256), "").is_err());
+
+    let client = Client::new("count_vector_indom_help_test").unwrap();
+    client.export(&mut [&mut cv]).unwrap();
+
+    let mmv = dump(client.mmv_path()).unwrap();
+    let (_, indom_blk) = mmv.indom_blks().iter().next().unwrap();
+    let help_text = |offset: &Option<u64>|
+        mmv.string_blks()[&offset.unwrap()].string().to_owned();
+    assert_eq!(help_text(indom_blk.short_help_offset()), "HTTP methods");
+    assert_eq!(help_text(indom_blk.long_help_offset()), "Methods of handled HTTP requests");
+}
@@ -0,0 +1,97 @@
+use super::*;
+
+/// A flag metric for a boolean value
+///
+/// Internally uses a `Metric<bool>` with `Semantics::Instant` and no
+/// dimensions, which is exported as a `U32` metric with value `0` or `1`
+#[derive(Debug)]
+pub struct Flag {
+    metric: Metric<bool>,
+    init_val: bool
+}
+
+impl Flag {
+    /// Creates a new flag metric with given initial value
+    pub fn new(name: &str, init_val: bool, shorthelp_text: &str, longhelp_text: &str) -> Result<Self, String> {
+        let metric = Metric::new(
+            name,
+            init_val,
+            Semantics::Instant,
+            Unit::new(),
+            shorthelp_text,
+            longhelp_text
+        )?;
+
+        Ok(Flag {
+            metric: metric,
+            init_val: init_val
+        })
+    }
+
+    /// Returns the current value of the flag
+    pub fn val(&self) -> bool {
+        *self.metric.val()
+    }
+
+    /// Sets the value of the flag
+    pub fn set(&mut self, val: bool) -> io::Result<()> {
+        self.metric.set_val(val)
+    }
+
+    /// Inverts the value of the flag
+    pub fn toggle(&mut self) -> io::Result<()> {
+        let val = *self.metric.val();
+        self.metric.set_val(!val)
+    }
+
+    /// Resets the flag to the initial value that was passed when
+    /// creating it
+    pub fn reset(&mut self) -> io::Result<()> {
+        self.metric.set_val(self.init_val)
+    }
+}
+
+impl MMVWriter for Flag {
+    private_impl!{}
+
+    fn write(&mut self, ws: &mut MMVWriterState, c: &mut Cursor<&mut [u8]>, mmv_ver: Version) -> io::Result<()> {
+        self.metric.write(ws, c, mmv_ver)
+    }
+
+    fn register(&self, ws: &mut MMVWriterState, mmv_ver: Version) -> io::Result<()> {
+        self.metric.register(ws, mmv_ver)
+    }
+
+    fn has_mmv2_string(&self) -> bool {
+        self.metric.has_mmv2_string()
+    }
+}
+
+#[test]
+pub fn test() {
+    use super::super::Client;
+    use super::super::super::mmv::{dump, TypedValue};
+
+    let mut flag = Flag::new("flag", false, "", "").unwrap();
+    assert!(!flag.val());
+
+    let client = Client::new("flag_test").unwrap();
+    client.export(&mut [&mut flag]).unwrap();
+    assert!(!flag.metric.read_mapped().unwrap());
+
+    flag.toggle().unwrap();
+    assert!(flag.val());
+    assert!(flag.metric.read_mapped().unwrap());
+
+    let mmv = dump(client.mmv_path()).unwrap();
+    let (_, metric) = mmv.metric_blks().iter().next().unwrap();
+    let (_, value) = mmv.value_blks().iter().next().unwrap();
+    assert_eq!(mmv.typed_value(metric, value), Some(TypedValue::U32(1)));
+
+    flag.toggle().unwrap();
+    assert!(!flag.metric.read_mapped().unwrap());
+
+    flag.set(true).unwrap();
+    flag.reset().unwrap();
+    assert!(!flag.val());
+}
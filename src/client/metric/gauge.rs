@@ -5,6 +5,7 @@ use super::*;
 ///
 /// Internally uses a `Metric<f64>` with `Semantics::Instant`,
 /// `Count::One` scale, and `1` count dimension
+#[derive(Debug)]
 pub struct Gauge {
     metric: Metric<f64>,
     init_val: f64
@@ -28,6 +29,22 @@ impl Gauge {
         })
     }
 
+    /// Wraps an existing metric, e.g, one created with a custom unit
+    ///
+    /// The result is an error if the metric has `Semantics::Counter`.
+    /// `reset` resets the gauge to the metric's initial value.
+    pub fn from_metric(metric: Metric<f64>) -> Result<Self, String> {
+        if let Semantics::Counter = metric.sem {
+            return Err(format!("metric {} has counter semantics", metric.name));
+        }
+
+        let init_val = metric.init_val;
+        Ok(Gauge {
+            metric: metric,
+            init_val: init_val
+        })
+    }
+
     /// Returns the current value of the gauge
     pub fn val(&self) -> f64 {
         *self.metric.val()
@@ -38,6 +55,14 @@ impl Gauge {
         self.metric.set_val(val)
     }
 
+    /// Sets the value of the gauge, returning an error if the
+    /// value is `NaN` or infinite
+    ///
+    /// `set`, `inc` and `dec` don't check for non-finite values.
+    pub fn set_finite(&mut self, val: f64) -> io::Result<()> {
+        self.metric.set_val_finite(val)
+    }
+
     /// Increments the gauge by the given value
     pub fn inc(&mut self, increment: f64) -> io::Result<()> {
         let val = *self.metric.val();
@@ -64,7 +89,7 @@ impl MMVWriter for Gauge {
         self.metric.write(ws, c, mmv_ver)
     }
 
-    fn register(&self, ws: &mut MMVWriterState, mmv_ver: Version) {
+    fn register(&self, ws: &mut MMVWriterState, mmv_ver: Version) -> io::Result<()> {
         self.metric.register(ws, mmv_ver)
     }
 
@@ -94,4 +119,24 @@ pub fn test() {
 
     gauge.reset().unwrap();
     assert_eq!(gauge.val(), 1.5);
+
+    assert!(gauge.set_finite(::std::f64::NAN).is_err());
+    assert_eq!(gauge.val(), 1.5);
+    gauge.set_finite(2.0).unwrap();
+    assert_eq!(gauge.val(), 2.0);
+}
+
+#[test]
+pub fn test_from_metric() {
+    let unit = Unit::new().space(Space::Byte, 1).unwrap();
+    let metric = Metric::new("custom_gauge", 1.0, Semantics::Discrete, unit, "", "").unwrap();
+    let mut gauge = Gauge::from_metric(metric).unwrap();
+
+    gauge.inc(2.5).unwrap();
+    assert_eq!(gauge.val(), 3.5);
+    gauge.reset().unwrap();
+    assert_eq!(gauge.val(), 1.0);
+
+    let metric = Metric::new("counting_gauge", 1.0, Semantics::Counter, unit, "", "").unwrap();
+    assert!(Gauge::from_metric(metric).is_err());
 }
@@ -1,14 +1,19 @@
+use std::collections::HashMap;
 use super::*;
 
+const MEAN_INST: &str = "__mean";
+
 /// A gauge vector for multiple floating point values with helper methods
 /// for incrementing and decrementing their value
 ///
 /// Internally uses an `InstanceMetric<f64>` with `Semantics::Instant` and
 /// `Count::One` scale, and `1` count dimension
+#[derive(Debug)]
 pub struct GaugeVector {
     im: InstanceMetric<f64>,
     indom: Indom,
-    init_val: f64
+    init_val: f64,
+    aggregate: bool
 }
 
 impl GaugeVector {
@@ -16,14 +21,14 @@ impl GaugeVector {
     pub fn new(name: &str, init_val: f64, instances: &[&str],
         shorthelp_text: &str, longhelp_text: &str) -> Result<Self, String> {
         
-        let indom_helptext = format!("Instance domain for GaugeVector '{}'", name);
+        let indom_helptext = indom_helptext("GaugeVector", name);
         let indom = Indom::new(instances, &indom_helptext, &indom_helptext)?;
         
         let im = InstanceMetric::new(
             &indom,
             name,
             init_val,
-            Semantics::Counter,
+            Semantics::Instant,
             Unit::new().count(Count::One, 1)?,
             shorthelp_text,
             longhelp_text
@@ -32,18 +37,109 @@ impl GaugeVector {
         Ok(GaugeVector {
             im: im,
             indom: indom,
-            init_val: init_val
+            init_val: init_val,
+            aggregate: false
         })
     }
 
+    /// Modifies and returns the gauge vector with an additional `__mean`
+    /// instance, which exports the mean of all other instances
+    ///
+    /// The exported mean is a convenience that's recomputed every time
+    /// an instance is updated through the gauge vector. This replaces
+    /// the internal instance domain and instance metric, so it should be
+    /// called before exporting the gauge vector. The result is an error
+    /// if the vector already has an instance named `__mean`.
+    pub fn with_aggregate(mut self) -> Result<Self, String> {
+        if self.aggregate {
+            return Ok(self);
+        }
+        if self.indom.has_instance(MEAN_INST) {
+            return Err(format!("instance {} already present", MEAN_INST));
+        }
+
+        let values = self.values();
+        let mut instances: Vec<&str> = values.keys().map(|i| i.as_str()).collect();
+        instances.push(MEAN_INST);
+        let indom = Indom::new(
+            &instances, &self.indom.shorthelp, &self.indom.longhelp
+        )?;
+
+        let mut im = {
+            let metric = &self.im.metric;
+            InstanceMetric::new(
+                &indom,
+                &metric.name,
+                self.init_val,
+                metric.sem,
+                Unit::from(metric.unit),
+                &metric.shorthelp,
+                &metric.longhelp
+            )?
+        };
+        // the new instance metric isn't exported yet, so these can't fail
+        for (instance, val) in &values {
+            im.set_val(instance, *val).unwrap().unwrap();
+        }
+
+        self.im = im;
+        self.indom = indom;
+        self.aggregate = true;
+        self.update_aggregate().unwrap();
+        Ok(self)
+    }
+
+    /// Returns the mean of the current gauges of all instances (other
+    /// than `__mean`), or `0` if there are none
+    pub fn mean(&self) -> f64 {
+        let (sum, count) = self.instances()
+            .fold((0.0, 0), |(sum, count), instance|
+                (sum + self.im.val(instance).unwrap(), count + 1)
+            );
+        if count == 0 { 0.0 } else { sum / count as f64 }
+    }
+
+    fn update_aggregate(&mut self) -> io::Result<()> {
+        if self.aggregate {
+            let mean = self.mean();
+            self.im.set_val(MEAN_INST, mean).unwrap()?;
+        }
+        Ok(())
+    }
+
+    fn instances<'a>(&'a self) -> impl Iterator<Item=&'a String> {
+        let aggregate = self.aggregate;
+        self.indom.instances_iter()
+            .filter(move |instance| !(aggregate && instance.as_str() == MEAN_INST))
+    }
+
+    fn is_settable(&self, instance: &str) -> bool {
+        !(self.aggregate && instance == MEAN_INST)
+    }
+
     /// Returns the current gauge of the instance
     pub fn val(&self, instance: &str) -> Option<f64> {
         self.im.val(instance).cloned()
     }
 
+    /// Returns the current gauge of every instance (other than `__mean`)
+    pub fn values(&self) -> HashMap<String, f64> {
+        self.instances()
+            .filter_map(|instance|
+                self.im.val(instance).map(|val| (instance.to_owned(), *val))
+            )
+            .collect()
+    }
+
     /// Sets the gauge of the instance
     pub fn set(&mut self, instance: &str, val: f64) -> Option<io::Result<()>> {
-        self.im.set_val(instance, val)
+        if !self.is_settable(instance) {
+            return None;
+        }
+        match self.im.set_val(instance, val)? {
+            Ok(()) => Some(self.update_aggregate()),
+            Err(err) => Some(Err(err))
+        }
     }
 
     /// Increments the gauge of the instance by the given value
@@ -51,7 +147,7 @@ impl GaugeVector {
     /// The wrapping `Option` is `None` if the instance wasn't found
     pub fn inc(&mut self, instance: &str, increment: f64) -> Option<io::Result<()>> {
         self.im.val(instance).cloned().and_then(|val|
-            self.im.set_val(instance, val + increment)
+            self.set(instance, val + increment)
         )
     }
 
@@ -64,11 +160,12 @@ impl GaugeVector {
 
     /// Increments the gauge of all instances by the given value
     pub fn inc_all(&mut self, increment: f64) -> io::Result<()> {
-        for instance in self.indom.instances_iter() {
-            let val = self.im.val(instance).cloned().unwrap();
-            self.im.set_val(instance, val + increment).unwrap()?;
+        let instances: Vec<String> = self.instances().cloned().collect();
+        for instance in instances {
+            let val = self.im.val(&instance).cloned().unwrap();
+            self.im.set_val(&instance, val + increment).unwrap()?;
         }
-        Ok(())
+        self.update_aggregate()
     }
 
     /// Decrements the gauge of all instances by the given value
@@ -81,19 +178,32 @@ impl GaugeVector {
     ///
     /// The wrapping `Option` is `None` if the instance wasn't found
     pub fn reset(&mut self, instance: &str) -> Option<io::Result<()>> {
-        self.im.set_val(instance, self.init_val)
+        let init_val = self.init_val;
+        self.set(instance, init_val)
     }
 
     /// Resets the gauge of all instances to the initial value that
     /// was passed when creating the vector
     pub fn reset_all(&mut self) -> io::Result<()> {
-        for instance in self.indom.instances_iter() {
-            self.im.set_val(instance, self.init_val).unwrap()?;
+        let instances: Vec<String> = self.instances().cloned().collect();
+        for instance in instances {
+            self.im.set_val(&instance, self.init_val).unwrap()?;
         }
-        Ok(())
+        self.update_aggregate()
+    }
+
+    /// Sets the short and long help texts of the internally created
+    /// instance domain, replacing the generated ones
+    ///
+    /// The result is an error if the length of `shorthelp` or
+    /// `longhelp` exceeds 255 bytes.
+    pub fn set_indom_help(&mut self, shorthelp: &str, longhelp: &str) -> Result<(), String> {
+        self.im.set_indom_help(shorthelp, longhelp)?;
+        self.indom.set_help(shorthelp, longhelp)
     }
 
-    /// Internally created instance domain
+    /// Internally created instance domain, including the `__mean`
+    /// instance of an aggregated gauge vector
     pub fn indom(&self) -> &Indom { &self.indom }
 }
 
@@ -104,7 +214,7 @@ impl MMVWriter for GaugeVector {
         self.im.write(ws, c, mmv_ver)
     }
 
-    fn register(&self, ws: &mut MMVWriterState, mmv_ver: Version) {
+    fn register(&self, ws: &mut MMVWriterState, mmv_ver: Version) -> io::Result<()> {
         self.im.register(ws, mmv_ver)
     }
 
@@ -156,4 +266,58 @@ pub fn test() {
     assert_eq!(gv.val("a").unwrap(), 1.5);   
     assert_eq!(gv.val("b").unwrap(), 1.5);
     assert_eq!(gv.val("c").unwrap(), 1.5);
+
+    gv.set("a", 0.5).unwrap().unwrap();
+    gv.set("b", 2.5).unwrap().unwrap();
+    let values = gv.values();
+    assert_eq!(values.len(), 3);
+    assert_eq!(values["a"], 0.5);
+    assert_eq!(values["b"], 2.5);
+    assert_eq!(values["c"], 1.5);
+}
+
+#[test]
+pub fn test_aggregate() {
+    use super::super::Client;
+
+    let mut gv = GaugeVector::new("gauge_vector_mean", 0.0, &["a", "b", "c"], "", "")
+        .unwrap().with_aggregate().unwrap();
+    assert_eq!(gv.indom().instance_count(), 4);
+
+    let client = Client::new("gauge_vector_aggregate_test").unwrap();
+    client.export(&mut [&mut gv]).unwrap();
+
+    gv.set("a", 1.0).unwrap().unwrap();
+    gv.set("b", 2.0).unwrap().unwrap();
+    gv.set("c", 6.0).unwrap().unwrap();
+    assert_eq!(gv.mean(), 3.0);
+    assert_eq!(gv.val(MEAN_INST), Some(3.0));
+    assert_eq!(gv.im.val(MEAN_INST), Some(&3.0));
+
+    gv.inc_all(1.0).unwrap();
+    assert_eq!(gv.val(MEAN_INST), Some(4.0));
+    assert!(gv.set(MEAN_INST, 0.0).is_none());
+    assert_eq!(gv.values().len(), 3);
+
+    gv.reset_all().unwrap();
+    assert_eq!(gv.val(MEAN_INST), Some(0.0));
+
+    let plain = GaugeVector::new("gauge_vector_plain", 1.0, &["a", "b"], "", "").unwrap();
+    assert_eq!(plain.mean(), 1.0);
+    assert!(GaugeVector::new("gauge_vector_clash", 1.0, &["__mean"], "", "")
+        .unwrap().with_aggregate().is_err());
+}
+
+#[test]
+pub fn test_semantics() {
+    use super::super::Client;
+    use super::super::super::mmv::dump;
+
+    let mut gv = GaugeVector::new("gauge_vector_sem", 0.0, &["a"], "", "").unwrap();
+    let client = Client::new("gauge_vector_sem_test").unwrap();
+    client.export(&mut [&mut gv]).unwrap();
+
+    let mmv = dump(client.mmv_path()).unwrap();
+    let (_, metric_blk) = mmv.metric_blks().iter().next().unwrap();
+    assert_eq!(metric_blk.sem(), Semantics::Instant as u32);
 }
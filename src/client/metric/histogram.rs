@@ -7,18 +7,33 @@ use hdrsample::Histogram as HdrHist;
 /// Internally backed by a [HDR Histogram](https://github.com/jonhoo/hdrsample),
 /// much of API and documentation being borrowed from it.
 ///
-/// Exports the `max`, `min`, `mean` and `stdev` statistics to an MMV
-/// by using an `InstanceMetric<f64>` with `Semantics::Instant`.
+/// Exports the `max`, `min`, `mean` and `stdev` statistics (and
+/// optionally the sample `count`, see `with_count`) to an MMV by using
+/// an `InstanceMetric<f64>` with `Semantics::Instant`.
 pub struct Histogram {
     im: InstanceMetric<f64>,
     indom: Indom,
-    histogram: HdrHist<u64>
+    histogram: HdrHist<u64>,
+    export_count: bool
+}
+
+impl fmt::Debug for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Histogram")
+            .field("im", &self.im)
+            .field("low", &self.low())
+            .field("high", &self.high())
+            .field("sigfig", &self.significant_figures())
+            .field("count", &self.count())
+            .finish()
+    }
 }
 
 const MAX_INST: &str = "max";
 const MIN_INST: &str = "min";
 const MEAN_INST: &str = "mean";
 const STDEV_INST: &str = "stdev";
+const COUNT_INST: &str = "count";
 
 const HIST_INSTANCES: &[&str] = &[MAX_INST, MIN_INST, MEAN_INST, STDEV_INST];
 
@@ -71,8 +86,8 @@ impl Histogram {
     pub fn new(name: &str, low: u64, high: u64, sigfig: u8, unit: Unit,
         shorthelp_text: &str, longhelp_text: &str) -> Result<Self, CreationError> {
     
-        let indom_helptext = format!("Instance domain for Histogram '{}'", name);
-        let indom = Indom::new(HIST_INSTANCES, &indom_helptext, &indom_helptext).unwrap();
+        let indom_helptext = indom_helptext("Histogram", name);
+        let indom = Indom::new(HIST_INSTANCES, &indom_helptext, &indom_helptext)?;
         
         let im = InstanceMetric::new(
             &indom,
@@ -90,15 +105,70 @@ impl Histogram {
         Ok(Histogram {
             im: im,
             indom: indom,
-            histogram: histogram
+            histogram: histogram,
+            export_count: false
         })
     }
 
+    /// Modifies and returns the histogram with an additional `count`
+    /// instance, which exports the total number of samples recorded
+    ///
+    /// This replaces the internal instance domain and instance metric,
+    /// so it should be called before exporting the histogram.
+    pub fn with_count(mut self) -> Self {
+        if self.export_count {
+            return self;
+        }
+
+        let mut instances = HIST_INSTANCES.to_vec();
+        instances.push(COUNT_INST);
+        let indom = Indom::new(
+            &instances, &self.indom.shorthelp, &self.indom.longhelp
+        ).unwrap();
+
+        let im = {
+            let metric = &self.im.metric;
+            InstanceMetric::new(
+                &indom,
+                &metric.name,
+                0.0,
+                metric.sem,
+                Unit::from(metric.unit),
+                &metric.shorthelp,
+                &metric.longhelp
+            ).unwrap()
+        };
+
+        self.im = im;
+        self.indom = indom;
+        self.export_count = true;
+        // the new instance metric isn't exported yet, so this can't fail
+        self.update_instances().unwrap();
+        self
+    }
+
+    // the instances are always present, but a bug that loses one should
+    // surface as an error from `record` rather than a panic
+    fn set_instance(&mut self, instance: &str, val: f64) -> io::Result<()> {
+        self.im.set_val(instance, val).unwrap_or_else(|| Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("histogram instance {} not found", instance)
+        )))
+    }
+
     fn update_instances(&mut self) -> io::Result<()> {
-        self.im.set_val(MIN_INST, self.histogram.min() as f64).unwrap()?;
-        self.im.set_val(MAX_INST, self.histogram.max() as f64).unwrap()?;
-        self.im.set_val(MEAN_INST, self.histogram.mean()).unwrap()?;
-        self.im.set_val(STDEV_INST, self.histogram.stdev()).unwrap()
+        if self.export_count {
+            let count = self.histogram.count() as f64;
+            self.set_instance(COUNT_INST, count)?;
+        }
+        let min = self.histogram.min() as f64;
+        let max = self.histogram.max() as f64;
+        let mean = self.histogram.mean();
+        let stdev = self.histogram.stdev();
+        self.set_instance(MIN_INST, min)?;
+        self.set_instance(MAX_INST, max)?;
+        self.set_instance(MEAN_INST, mean)?;
+        self.set_instance(STDEV_INST, stdev)
     }
 
     /// Records a value
@@ -153,12 +223,40 @@ impl Histogram {
         self.histogram.value_at_percentile(percentile)
     }
 
+    /// Returns the values at several percentiles, paired with the
+    /// percentile and in the given order
+    ///
+    /// These are computed on demand, and aren't exported as instances.
+    pub fn percentiles(&self, percentiles: &[f64]) -> Vec<(f64, u64)> {
+        percentiles.iter()
+            .map(|&p| (p, self.histogram.value_at_percentile(p)))
+            .collect()
+    }
+
+    /// Returns an iterator over the buckets of the HDR histogram that
+    /// have recorded values, yielding the (highest equivalent) value of
+    /// each bucket and the count of values recorded in it
+    pub fn recorded_values<'a>(&'a self) -> impl Iterator<Item = (u64, u64)> + 'a {
+        self.histogram.iter_recorded()
+            .map(|v| (v.value(), v.count_at_value()))
+    }
+
     /// Control whether or not the histogram can auto-resize and auto-adjust
     /// it's highest trackable value as high-valued samples are recorded
     pub fn set_autoresize(&mut self, enable: bool) {
         self.histogram.auto(enable);
     }
 
+    /// Sets the short and long help texts of the internally created
+    /// instance domain, replacing the generated ones
+    ///
+    /// The result is an error if the length of `shorthelp` or
+    /// `longhelp` exceeds 255 bytes.
+    pub fn set_indom_help(&mut self, shorthelp: &str, longhelp: &str) -> Result<(), String> {
+        self.im.set_indom_help(shorthelp, longhelp)?;
+        self.indom.set_help(shorthelp, longhelp)
+    }
+
     /// Internally created instance domain
     pub fn indom(&self) -> &Indom { &self.indom }
 
@@ -173,7 +271,7 @@ impl MMVWriter for Histogram {
         self.im.write(ws, c, mmv_ver)
     }
 
-    fn register(&self, ws: &mut MMVWriterState, mmv_ver: Version) {
+    fn register(&self, ws: &mut MMVWriterState, mmv_ver: Version) -> io::Result<()> {
         self.im.register(ws, mmv_ver)
     }
 
@@ -231,3 +329,88 @@ pub fn test() {
         hist.histogram.stdev()
     );
 }
+
+#[test]
+pub fn test_with_count() {
+    use super::super::Client;
+
+    let mut hist = Histogram::new(
+        "histogram_with_count",
+        1, 100, 2,
+        Unit::new(),
+        "", ""
+    ).unwrap().with_count();
+    assert!(hist.indom().instances().any(|instance| instance == COUNT_INST));
+    assert_eq!(hist.im.val(COUNT_INST), Some(&0.0));
+
+    Client::new("histogram_with_count_test").unwrap()
+        .export(&mut [&mut hist]).unwrap();
+
+    let n = 42;
+    for val in 0..n {
+        hist.record(val % 100 + 1).unwrap();
+    }
+    assert_eq!(hist.im.val(COUNT_INST), Some(&(n as f64)));
+
+    hist.reset().unwrap();
+    assert_eq!(hist.im.val(COUNT_INST), Some(&0.0));
+}
+
+#[test]
+pub fn test_long_names() {
+    let name: String = ::std::iter::repeat('h').take(60).collect();
+    assert!(Histogram::new(&name, 1, 100, 2, Unit::new(), "", "").is_ok());
+
+    // the generated indom help text is longer than the name
+    let name: String = ::std::iter::repeat('h').take(STRING_BLOCK_LEN as usize - 1).collect();
+    let hist = Histogram::new(&name, 1, 100, 2, Unit::new(), "", "").unwrap();
+    assert!(hist.indom().shorthelp.len() < STRING_BLOCK_LEN as usize);
+
+    let name: String = ::std::iter::repeat('h').take(STRING_BLOCK_LEN as usize).collect();
+    match Histogram::new(&name, 1, 100, 2, Unit::new(), "", "") {
+        Err(CreationError::Instance(_)) => {},
+        _ => panic!("expected an instance error")
+    }
+}
+
+#[test]
+pub fn test_percentiles() {
+    let mut hist = Histogram::new("histogram_percentiles", 1, 1000, 3, Unit::new(), "", "").unwrap();
+    for val in 1..1001 {
+        hist.record(val).unwrap();
+    }
+    hist.record_n(500, 10).unwrap();
+
+    let ps = [10.0, 25.0, 50.0, 75.0, 90.0, 99.0, 100.0];
+    let vals = hist.percentiles(&ps);
+    assert_eq!(vals.len(), ps.len());
+    for (&p, &(q, val)) in ps.iter().zip(vals.iter()) {
+        assert_eq!(p, q);
+        assert_eq!(val, hist.value_at_percentile(p));
+    }
+    for pair in vals.windows(2) {
+        assert!(pair[0].1 <= pair[1].1);
+    }
+
+    let recorded: Vec<(u64, u64)> = hist.recorded_values().collect();
+    assert_eq!(recorded.iter().map(|&(_, count)| count).sum::<u64>(), hist.count());
+    for pair in recorded.windows(2) {
+        assert!(pair[0].0 < pair[1].0);
+    }
+}
+
+#[test]
+pub fn test_record_ok() {
+    use super::super::Client;
+
+    let mut hist = Histogram::new("histogram_record_ok", 1, 100, 2, Unit::new(), "", "")
+        .unwrap().with_count();
+    Client::new("histogram_record_ok_test").unwrap()
+        .export(&mut [&mut hist]).unwrap();
+
+    assert!(hist.record(10).is_ok());
+    assert!(hist.record_n(20, 3).is_ok());
+    assert!(hist.reset().is_ok());
+    assert!(hist.set_instance("p99", 1.0).is_err());
+    assert_eq!(hist.im.val(MAX_INST), Some(&0.0));
+}
@@ -1,16 +1,19 @@
-use byteorder::WriteBytesExt;
+use byteorder::{ReadBytesExt, WriteBytesExt};
 use memmap::{Mmap, MmapViewSync, Protection};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::collections::hash_map::{DefaultHasher, HashMap};
 use std::collections::hash_set::Iter;
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::io;
-use std::io::{Write, Cursor};
+use std::io::{Read, Write, Cursor};
 use std::mem;
 use std::str;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use super::super::mmv::{MTCode, Version};
+use super::ExportedMetricInfo;
 use super::super::{
     Endian,
     ITEM_BIT_LEN,
@@ -29,6 +32,9 @@ use super::super::{
 mod counter;
 pub use self::counter::Counter;
 
+mod signedcounter;
+pub use self::signedcounter::SignedCounter;
+
 mod gauge;
 pub use self::gauge::Gauge;
 
@@ -46,8 +52,34 @@ pub use self::histogram::Histogram;
 pub use self::histogram::CreationError as HistCreationError;
 pub use self::histogram::RecordError as HistRecordError;
 
+mod summary;
+pub use self::summary::Summary;
+
+mod ring;
+pub use self::ring::RingMetric;
+
+mod flag;
+pub use self::flag::Flag;
+
+mod set;
+pub use self::set::MetricSet;
+
+mod timestamped;
+pub use self::timestamped::TimestampedMetric;
+
+mod shared;
+pub use self::shared::SharedMetric;
+
+mod atomic;
+pub use self::atomic::AtomicMetric;
+
+mod batch;
+pub use self::batch::BatchUpdate;
+
+pub mod units;
+
 mod private {
-    use byteorder::WriteBytesExt;
+    use byteorder::{ReadBytesExt, WriteBytesExt};
     use std::io;
 
     /// Generic type for any Metric's value
@@ -62,10 +94,26 @@ mod private {
         ///
         /// For the string type, the UTF-8 byte sequence is suffixed with a null byte.
         fn write<W: WriteBytesExt>(&self, writer: &mut W) -> io::Result<()>;
+        /// Reads a value from it's byte representation in a reader.
+        ///
+        /// The byte sequence is expected in the same format that `write` produces.
+        fn read<R: ReadBytesExt>(reader: &mut R) -> io::Result<Self> where Self: Sized;
+        /// Returns the value that signals "no value available" to
+        /// pmdammv when the `SENTINEL` flag is set
+        fn sentinel() -> Self where Self: Sized;
+        /// Writes the "no value available" sentinel to a writer.
+        ///
+        /// This is the byte representation of `sentinel()`, unless the
+        /// type has no spare value for it, in which case a raw pattern
+        /// that `write` never produces is written instead.
+        fn write_sentinel<W: WriteBytesExt>(writer: &mut W) -> io::Result<()> where Self: Sized {
+            Self::sentinel().write(writer)
+        }
     }
 
     use memmap::MmapViewSync;
-    use std::collections::HashMap;
+    use std::collections::{HashMap, HashSet};
+    use super::super::ExportedMetricInfo;
     
     pub struct MMVWriterState {
         // Mmap view of the entier MMV file
@@ -89,6 +137,8 @@ mod private {
         //
         pub indom_cache: HashMap<u32, Option<HashMap<String, u64>>>, // (indom_id, offsets to it's instances)
         // if the offsets vector is None, it means the instances haven't been written yet
+        //
+        pub indom_instances: HashMap<u32, HashSet<String>>, // (indom_id, instances registered with it)
 
         // offsets to blocks
         pub indom_sec_off: u64,
@@ -105,9 +155,20 @@ mod private {
         pub value_blk_idx: u64,
         pub string_blk_idx: u64,
 
+        // prefix of exported metric names, without the separating '.'
+        pub name_prefix: Option<String>,
+
+        // whether metrics are pointed to their values in `mmap_view`;
+        // if not, they keep mapping what they mapped before
+        pub remap_metrics: bool,
+
         // mmv header data
         pub flags: u32,
-        pub cluster_id: u32
+        pub pid: i32,
+        pub cluster_id: u32,
+
+        // metrics written so far
+        pub exported_metrics: Vec<ExportedMetricInfo>
     }
 
     impl MMVWriterState {
@@ -126,6 +187,7 @@ mod private {
                 n_instances: 0,
 
                 indom_cache: HashMap::new(),
+                indom_instances: HashMap::new(),
                 non_value_string_cache: HashMap::new(),
 
                 indom_sec_off: 0,
@@ -141,8 +203,15 @@ mod private {
                 value_blk_idx: 0,
                 string_blk_idx: 0,
 
+                name_prefix: None,
+
+                remap_metrics: true,
+
                 flags: 0,
-                cluster_id: 0
+                pid: 0,
+                cluster_id: 0,
+
+                exported_metrics: Vec::new()
             }
         }
     }
@@ -157,7 +226,7 @@ mod private {
             writer_state: &mut MMVWriterState,
             cursor: &mut io::Cursor<&mut [u8]>, mmv_ver: Version) -> io::Result<()>;
 
-        fn register(&self, ws: &mut MMVWriterState, mmv_ver: Version);
+        fn register(&self, ws: &mut MMVWriterState, mmv_ver: Version) -> io::Result<()>;
 
         fn has_mmv2_string(&self) -> bool;
     }
@@ -167,7 +236,7 @@ pub (super) use self::private::MetricType;
 pub (super) use self::private::{MMVWriter, MMVWriterState};
 
 macro_rules! impl_metric_type_for (
-    ($typ:tt, $base_typ:tt, $type_code:expr) => (
+    ($typ:tt, $base_typ:tt, $type_code:expr, $sentinel:expr) => (
         impl MetricType for $typ {
 
             private_impl!{}
@@ -185,16 +254,52 @@ macro_rules! impl_metric_type_for (
                 )
             }
 
+            fn read<R: ReadBytesExt>(r: &mut R) -> io::Result<Self> {
+                let base_val = r.read_u64::<super::Endian>()? as $base_typ;
+                Ok(unsafe { mem::transmute::<$base_typ, $typ>(base_val) })
+            }
+
+            fn sentinel() -> Self {
+                $sentinel
+            }
+
         }
     )
 );
 
-impl_metric_type_for!(i32, u32, MTCode::I32);
-impl_metric_type_for!(u32, u32, MTCode::U32);
-impl_metric_type_for!(i64, u64, MTCode::I64);
-impl_metric_type_for!(u64, u64, MTCode::U64);
-impl_metric_type_for!(f32, u32, MTCode::F32);
-impl_metric_type_for!(f64, u64, MTCode::F64);
+impl_metric_type_for!(i32, u32, MTCode::I32, i32::MIN);
+impl_metric_type_for!(u32, u32, MTCode::U32, u32::MAX);
+impl_metric_type_for!(i64, u64, MTCode::I64, i64::MIN);
+impl_metric_type_for!(u64, u64, MTCode::U64, u64::MAX);
+impl_metric_type_for!(f32, u32, MTCode::F32, f32::NAN);
+impl_metric_type_for!(f64, u64, MTCode::F64, f64::NAN);
+
+/// Booleans are exported as `U32` metrics with value `0` or `1`
+impl MetricType for bool {
+    private_impl!{}
+
+    fn type_code(&self) -> u32 {
+        MTCode::U32 as u32
+    }
+
+    fn write<W: WriteBytesExt>(&self, w: &mut W) -> io::Result<()> {
+        w.write_u64::<super::Endian>(*self as u64)
+    }
+
+    fn read<R: ReadBytesExt>(r: &mut R) -> io::Result<Self> {
+        Ok(r.read_u64::<super::Endian>()? as u32 != 0)
+    }
+
+    // there's no spare boolean value to use as a sentinel, so
+    // `write_sentinel` writes the maximum U32 instead
+    fn sentinel() -> Self {
+        false
+    }
+
+    fn write_sentinel<W: WriteBytesExt>(w: &mut W) -> io::Result<()> {
+        w.write_u64::<super::Endian>(u32::MAX as u64)
+    }
+}
 
 impl MetricType for String {
     private_impl!{}
@@ -204,12 +309,107 @@ impl MetricType for String {
     }
 
     fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        // checked upfront so an MMV string block is never left
+        // partially written without a null terminator
+        if self.len() >= STRING_BLOCK_LEN as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("string value longer than {} bytes", STRING_BLOCK_LEN - 1)
+            ));
+        }
         writer.write_all(self.as_bytes())?;
         writer.write_all(&[0])
     }
+
+    fn read<R: ReadBytesExt>(reader: &mut R) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        for byte in reader.by_ref().bytes().take(STRING_BLOCK_LEN as usize) {
+            let byte = byte?;
+            if byte == 0 {
+                break;
+            }
+            bytes.push(byte);
+        }
+        String::from_utf8(bytes).map_err(|err|
+            io::Error::new(io::ErrorKind::InvalidData, err)
+        )
+    }
+
+    fn sentinel() -> Self {
+        String::new()
+    }
+}
+
+/// Durations are exported as `U64` metrics in nanoseconds, saturating
+/// at `u64::MAX`
+impl MetricType for Duration {
+    private_impl!{}
+
+    fn type_code(&self) -> u32 {
+        MTCode::U64 as u32
+    }
+
+    fn write<W: WriteBytesExt>(&self, w: &mut W) -> io::Result<()> {
+        let nanos = self.as_secs().checked_mul(1_000_000_000)
+            .and_then(|nanos| nanos.checked_add(self.subsec_nanos() as u64))
+            .unwrap_or(u64::MAX);
+        w.write_u64::<super::Endian>(nanos)
+    }
+
+    fn read<R: ReadBytesExt>(r: &mut R) -> io::Result<Self> {
+        let nanos = r.read_u64::<super::Endian>()?;
+        Ok(Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32))
+    }
+
+    // the longest exportable duration, which is also what any longer
+    // duration saturates to, so it can't be told apart from the sentinel
+    fn sentinel() -> Self {
+        Duration::new(u64::MAX / 1_000_000_000, (u64::MAX % 1_000_000_000) as u32)
+    }
+}
+
+/// Points in time are exported as `I64` metrics in whole seconds since
+/// the Unix epoch, truncated towards the epoch
+impl MetricType for SystemTime {
+    private_impl!{}
+
+    fn type_code(&self) -> u32 {
+        MTCode::I64 as u32
+    }
+
+    fn write<W: WriteBytesExt>(&self, w: &mut W) -> io::Result<()> {
+        let secs = match self.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs().min(i64::MAX as u64) as i64,
+            Err(err) => -(err.duration().as_secs().min(i64::MAX as u64) as i64)
+        };
+        w.write_i64::<super::Endian>(secs)
+    }
+
+    fn read<R: ReadBytesExt>(r: &mut R) -> io::Result<Self> {
+        let secs = r.read_i64::<super::Endian>()?;
+        let time = if secs >= 0 {
+            UNIX_EPOCH.checked_add(Duration::from_secs(secs as u64))
+        } else {
+            UNIX_EPOCH.checked_sub(Duration::from_secs(secs.wrapping_neg() as u64))
+        };
+        time.ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} seconds from the epoch isn't representable", secs)
+        ))
+    }
+
+    // not every i64 is a representable time, so the epoch is used,
+    // and `write_sentinel` writes the minimum I64 instead
+    fn sentinel() -> Self {
+        UNIX_EPOCH
+    }
+
+    fn write_sentinel<W: WriteBytesExt>(w: &mut W) -> io::Result<()> {
+        w.write_i64::<super::Endian>(i64::MIN)
+    }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 /// Scale for the space component of a unit
 pub enum Space {
     /// byte
@@ -257,7 +457,7 @@ impl fmt::Display for Space {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 /// Scale for the time component of a unit
 pub enum Time {
     /// nanosecond
@@ -301,8 +501,11 @@ impl fmt::Display for Time {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 /// Scale for the count component of a unit
+///
+/// PCP currently only defines the `One` scale. Other 4-bit count scales
+/// used by third-party agents can be set with `Unit::with_count_scale`.
 pub enum Count {
     One = 0
 }
@@ -324,7 +527,7 @@ impl fmt::Display for Count {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq)]
 /// Unit for a Metric
 pub struct Unit {
     /*
@@ -365,6 +568,11 @@ impl Unit {
         }
     }
 
+    /// Returns the raw PMAPI representation of the unit
+    pub fn pmapi_repr(&self) -> u32 {
+        self.pmapi_repr
+    }
+
     /// Returns an empty unit with all dimensions set to `0`
     /// and all scales set to an undefined variant
     pub fn new() -> Self {
@@ -395,18 +603,47 @@ impl Unit {
         Ok(self)
     }
 
-    fn space_scale(&self) -> u8 {
+    /// Modifies and returns the unit with given raw 4-bit count scale,
+    /// for interoperating with agents that use scales other than
+    /// those in `Count`
+    ///
+    /// The count dimension is left unchanged.
+    pub fn with_count_scale(mut self, scale: u8) -> Result<Self, String> {
+        if scale as u32 > LS_FOUR_BIT_MASK {
+            return Err(format!("Count scale {} is out of range [0, 15]", scale))
+        }
+        self.pmapi_repr &= !(LS_FOUR_BIT_MASK << COUNT_SCALE_LSB);
+        self.pmapi_repr |= (scale as u32) << COUNT_SCALE_LSB;
+        Ok(self)
+    }
+
+    fn raw_space_scale(&self) -> u8 {
         ((self.pmapi_repr >> SPACE_SCALE_LSB) & LS_FOUR_BIT_MASK) as u8
     }
 
-    fn time_scale(&self) -> u8 {
+    fn raw_time_scale(&self) -> u8 {
         ((self.pmapi_repr >> TIME_SCALE_LSB) & LS_FOUR_BIT_MASK) as u8
     }
 
-    fn count_scale(&self) -> u8 {
+    fn raw_count_scale(&self) -> u8 {
         ((self.pmapi_repr >> COUNT_SCALE_LSB) & LS_FOUR_BIT_MASK) as u8
     }
 
+    /// Returns the space scale, or `None` if it isn't a known `Space`
+    pub fn space_scale(&self) -> Option<Space> {
+        Space::from_u8(self.raw_space_scale())
+    }
+
+    /// Returns the time scale, or `None` if it isn't a known `Time`
+    pub fn time_scale(&self) -> Option<Time> {
+        Time::from_u8(self.raw_time_scale())
+    }
+
+    /// Returns the count scale, or `None` if it isn't a known `Count`
+    pub fn count_scale(&self) -> Option<Count> {
+        Count::from_u8(self.raw_count_scale())
+    }
+
     /*
         We have a 4-bit value in two's complement form which we have to
         sign-extend to 8 bits. We first left shift our 4 bits in pmapi_repr
@@ -425,22 +662,37 @@ impl Unit {
         ) as i8
     }
 
-    fn space_dim(&self) -> i8 {
+    /// Returns the space dimension
+    pub fn space_dim(&self) -> i8 {
         self.dim(SPACE_DIM_LSB)
     }
 
-    fn time_dim(&self) -> i8 {
+    /// Returns the time dimension
+    pub fn time_dim(&self) -> i8 {
         self.dim(TIME_DIM_LSB)
     }
 
-    fn count_dim(&self) -> i8 {
+    /// Returns the count dimension
+    pub fn count_dim(&self) -> i8 {
         self.dim(COUNT_DIM_LSB)
     }
 }
 
+impl From<u32> for Unit {
+    fn from(pmapi_repr: u32) -> Unit {
+        Unit::from_raw(pmapi_repr)
+    }
+}
+
+impl From<Unit> for u32 {
+    fn from(unit: Unit) -> u32 {
+        unit.pmapi_repr
+    }
+}
+
 macro_rules! write_dim (
-    ($dim:expr, $scale:expr, $scale_type:tt, $f:expr) => (
-        if let Some(dim_scale) = $scale_type::from_u8($scale) {
+    ($dim:expr, $scale:expr, $f:expr) => (
+        if let Some(dim_scale) = $scale {
             write!($f, "{}", dim_scale)?;
             if $dim.abs() > 1 {
                 write!($f, "^{}", $dim.abs())?;
@@ -450,8 +702,8 @@ macro_rules! write_dim (
     )
 );
 
-impl fmt::Display for Unit {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl Unit {
+    fn write_dims<W: fmt::Write>(&self, f: &mut W) -> fmt::Result {
         let space_dim = self.space_dim();
         let space_scale = self.space_scale();
         let time_dim = self.time_dim();
@@ -460,33 +712,55 @@ impl fmt::Display for Unit {
         let count_scale = self.count_scale();
 
         if space_dim > 0 {
-            write_dim!(space_dim, space_scale, Space, f);
+            write_dim!(space_dim, space_scale, f);
         }
         if time_dim > 0 {
-            write_dim!(time_dim, time_scale, Time, f);
+            write_dim!(time_dim, time_scale, f);
         }
         if count_dim > 0 {
-            write_dim!(count_dim, count_scale, Count, f);
+            write_dim!(count_dim, count_scale, f);
         }
 
         if space_dim < 0 || time_dim < 0 || count_dim < 0 {
             write!(f, "/ ")?;
             if space_dim < 0 {
-                write_dim!(space_dim, space_scale, Space, f);
+                write_dim!(space_dim, space_scale, f);
             }
             if time_dim < 0 {
-                write_dim!(time_dim, time_scale, Time, f);
+                write_dim!(time_dim, time_scale, f);
             }
             if count_dim < 0 {
-                write_dim!(count_dim, count_scale, Count, f);
+                write_dim!(count_dim, count_scale, f);
             }
         }
 
+        Ok(())
+    }
+
+    /// Returns the dimensions and scales of the unit as a human
+    /// readable string (e.g, `"KiB / sec"`), without the hexadecimal
+    /// representation that `Display` appends
+    pub fn describe(&self) -> String {
+        let mut s = String::new();
+        self.write_dims(&mut s).unwrap();
+        s.trim_end().to_owned()
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_dims(f)?;
         write!(f, "(0x{:x})", self.pmapi_repr)
     }
 }
 
-#[derive(Copy, Clone)]
+impl fmt::Debug for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unit({})", self)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
 /// Semantic for a Metric
 pub enum Semantics {
     /// Counter
@@ -506,16 +780,50 @@ impl Semantics {
             _ => None
         }
     }
+
+    /// Returns the name of the semantics, without the numeric code
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Semantics::Counter => "counter",
+            Semantics::Instant => "instant",
+            Semantics::Discrete => "discrete"
+        }
+    }
+}
+
+impl str::FromStr for Semantics {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "counter" => Ok(Semantics::Counter),
+            "instant" => Ok(Semantics::Instant),
+            "discrete" => Ok(Semantics::Discrete),
+            _ => Err(format!("Invalid semantics '{}'", s))
+        }
+    }
 }
 
 impl fmt::Display for Semantics {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            Semantics::Counter => write!(f, "counter")?,
-            Semantics::Instant => write!(f, "instant")?,
-            Semantics::Discrete => write!(f, "discrete")?
-        }
-        write!(f, " (0x{:x})", *self as u32)
+        write!(f, "{} (0x{:x})", self.as_str(), *self as u32)
+    }
+}
+
+// pmdammv maps metric names into the PMNS, where empty names and
+// characters other than these (e.g, spaces or '/') break the namespace;
+// dots separate levels of the hierarchy
+pub (super) fn validate_name(kind: &str, name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err(format!("{} name is empty", kind));
+    }
+    let is_valid = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.';
+    match name.chars().find(|&c| !is_valid(c)) {
+        Some(c) => Err(format!(
+            "{} name {:?} contains invalid character {:?}; only A-Z, a-z, 0-9, '_' and '.' are allowed",
+            kind, name, c
+        )),
+        None => Ok(())
     }
 }
 
@@ -529,7 +837,65 @@ pub struct Metric<T> {
     shorthelp: String,
     longhelp: String,
     val: T,
-    mmap_view: MmapViewSync
+    init_val: T,
+    mmap_view: MmapViewSync,
+    on_change: Option<Box<FnMut(&T) + Send + Sync>>,
+    labels: Vec<(String, String)>
+}
+
+impl<T: fmt::Debug> fmt::Debug for Metric<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Metric")
+            .field("name", &self.name)
+            .field("item", &self.item)
+            .field("sem", &self.sem)
+            .field("indom", &self.indom)
+            .field("unit", &Unit::from(self.unit))
+            .field("shorthelp", &self.shorthelp)
+            .field("longhelp", &self.longhelp)
+            .field("val", &self.val)
+            .field("labels", &self.labels)
+            .finish()
+    }
+}
+
+impl Metric<()> {
+    /// Returns the item that a metric with the given name is assigned,
+    /// regardless of it's type
+    ///
+    /// Together with the client's cluster ID, the item makes up the
+    /// PMID of the metric.
+    pub fn item_for_name(name: &str) -> u32 {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(name.as_bytes());
+        (hasher.finish() as u32) & ((1 << ITEM_BIT_LEN) - 1)
+    }
+}
+
+impl Metric<Duration> {
+    /// Creates a new duration metric, exported in nanoseconds
+    ///
+    /// The unit is set to nanoseconds; see `Metric::new` for the errors.
+    pub fn new_duration(
+        name: &str, init_val: Duration, sem: Semantics,
+        shorthelp: &str, longhelp: &str) -> Result<Self, String> {
+
+        let unit = Unit::new().time(Time::NSec, 1).unwrap();
+        Metric::new(name, init_val, sem, unit, shorthelp, longhelp)
+    }
+}
+
+impl Metric<SystemTime> {
+    /// Creates a new timestamp metric, exported in seconds since the
+    /// Unix epoch with `Discrete` semantics
+    ///
+    /// The unit is set to seconds; see `Metric::new` for the errors.
+    pub fn new_timestamp(
+        name: &str, init_val: SystemTime,
+        shorthelp: &str, longhelp: &str) -> Result<Self, String> {
+
+        Metric::new(name, init_val, Semantics::Discrete, units::seconds(), shorthelp, longhelp)
+    }
 }
 
 lazy_static! {
@@ -551,6 +917,7 @@ impl<T: MetricType + Clone> Metric<T> {
         if name.len() >= STRING_BLOCK_LEN as usize {
             return Err(format!("name longer than {} bytes", STRING_BLOCK_LEN - 1));
         }
+        validate_name("metric", name)?;
         if shorthelp.len() >= STRING_BLOCK_LEN as usize {
             return Err(format!("short help text longer than {} bytes", STRING_BLOCK_LEN - 1));
         }
@@ -558,24 +925,25 @@ impl<T: MetricType + Clone> Metric<T> {
             return Err(format!("long help text longer than {} bytes", STRING_BLOCK_LEN - 1));
         }
 
-        let mut hasher = DefaultHasher::new();
-        hasher.write(name.as_bytes());
-        let item = (hasher.finish() as u32) & ((1 << ITEM_BIT_LEN) - 1);
-
         Ok(Metric {
             name: name.to_owned(),
-            item: item,
+            item: Metric::item_for_name(name),
             sem: sem,
             indom: 0,
             unit: unit.pmapi_repr,
             shorthelp: shorthelp.to_owned(),
             longhelp: longhelp.to_owned(),
-            val: init_val,
-            mmap_view: unsafe { SCRATCH_VIEW.clone() }
+            val: init_val.clone(),
+            init_val: init_val,
+            mmap_view: unsafe { SCRATCH_VIEW.clone() },
+            on_change: None,
+            labels: Vec::new()
         })
     }
 
-    /// Returns the current value of the metric
+    /// Returns a reference to the current value of the metric
+    ///
+    /// The value isn't cloned, so this is cheap for `String` metrics too.
     pub fn val(&self) -> &T {
         &self.val
     }    
@@ -587,11 +955,106 @@ impl<T: MetricType + Clone> Metric<T> {
     ///
     /// If the metric isn't exported, this method will still
     /// succeed and update the value.
+    ///
+    /// The result is an error, and the value is left unchanged, if
+    /// a `String` value exceeds 255 bytes.
     pub fn set_val(&mut self, new_val: T) -> io::Result<()> {
         new_val.write(unsafe { &mut self.mmap_view.as_mut_slice() })?;
         self.val = new_val;
+        if let Some(ref mut on_change) = self.on_change {
+            on_change(&self.val);
+        }
+        Ok(())
+    }
+
+    /// Resets the metric to the initial value that was passed when
+    /// creating it, in the same way as `set_val`
+    pub fn reset(&mut self) -> io::Result<()> {
+        let init_val = self.init_val.clone();
+        self.set_val(init_val)
+    }
+
+    /// Registers a callback that's called with the new value every
+    /// time `set_val` succeeds, replacing any previous callback
+    ///
+    /// The callback isn't called for values written to the MMV by
+    /// other processes.
+    pub fn on_change(&mut self, f: Box<FnMut(&T) + Send + Sync>) {
+        self.on_change = Some(f);
+    }
+
+    /// Reads the current value of the metric from the memory-mapped
+    /// MMV file
+    ///
+    /// Unlike `val`, this reflects values written to the MMV by other
+    /// processes (e.g, `pmstore`). If the metric isn't exported, the
+    /// returned value is meaningless.
+    pub fn read_mapped(&self) -> io::Result<T> {
+        T::read(&mut unsafe { self.mmap_view.as_slice() })
+    }
+
+    /// Sets the short help text of the metric
+    ///
+    /// The result is an error if the length of `shorthelp` exceeds
+    /// 255 bytes. Setting the help text of an already exported metric
+    /// has no effect on the MMV until it's exported again.
+    pub fn set_shorthelp(&mut self, shorthelp: &str) -> Result<(), String> {
+        if shorthelp.len() >= STRING_BLOCK_LEN as usize {
+            return Err(format!("short help text longer than {} bytes", STRING_BLOCK_LEN - 1));
+        }
+        self.shorthelp = shorthelp.to_owned();
+        Ok(())
+    }
+
+    /// Sets the long help text of the metric
+    ///
+    /// The result is an error if the length of `longhelp` exceeds
+    /// 255 bytes. Setting the help text of an already exported metric
+    /// has no effect on the MMV until it's exported again.
+    pub fn set_longhelp(&mut self, longhelp: &str) -> Result<(), String> {
+        if longhelp.len() >= STRING_BLOCK_LEN as usize {
+            return Err(format!("long help text longer than {} bytes", STRING_BLOCK_LEN - 1));
+        }
+        self.longhelp = longhelp.to_owned();
+        Ok(())
+    }
+
+    /// Sets the labels of the metric, replacing any previous labels
+    ///
+    /// Label names must start with a letter and only contain letters,
+    /// digits and `_`, and must be unique. Values are limited to 255
+    /// bytes. The result is an error, and the labels are left
+    /// unchanged, if any label is invalid.
+    ///
+    /// Labels are currently only kept in memory. MMV versions 1 and 2,
+    /// which are the ones written by hornet, have no section for them;
+    /// they're meant to be written to the label section of MMV version
+    /// 3 once it's supported.
+    pub fn with_labels(&mut self, labels: &[(&str, &str)]) -> Result<(), String> {
+        let mut names = HashSet::new();
+        for &(name, value) in labels {
+            let mut chars = name.chars();
+            let is_valid_name = chars.next().map_or(false, |c| c.is_ascii_alphabetic())
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !is_valid_name {
+                return Err(format!("invalid label name {:?}", name));
+            }
+            if !names.insert(name) {
+                return Err(format!("duplicate label name {:?}", name));
+            }
+            if value.len() >= STRING_BLOCK_LEN as usize {
+                return Err(format!("label value longer than {} bytes", STRING_BLOCK_LEN - 1));
+            }
+        }
+
+        self.labels = labels.iter()
+            .map(|&(name, value)| (name.to_owned(), value.to_owned()))
+            .collect();
         Ok(())
     }
+
+    /// Returns the labels of the metric, in the order they were set
+    pub fn labels(&self) -> &[(String, String)] { &self.labels }
     
     pub fn name(&self) -> &str { &self.name }
     pub fn item(&self) -> u32 { self.item }
@@ -603,7 +1066,43 @@ impl<T: MetricType + Clone> Metric<T> {
     pub fn longhelp(&self) -> &str { &self.longhelp }
 }
 
-#[derive(Clone)]
+macro_rules! impl_set_val_finite_for (
+    ($typ:tt) => (
+        impl Metric<$typ> {
+            /// Sets the current value of the metric, like `set_val`,
+            /// but returns an error if the value is `NaN` or infinite
+            pub fn set_val_finite(&mut self, new_val: $typ) -> io::Result<()> {
+                if !new_val.is_finite() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("non-finite value {}", new_val)
+                    ));
+                }
+                self.set_val(new_val)
+            }
+        }
+    )
+);
+
+impl_set_val_finite_for!(f32);
+impl_set_val_finite_for!(f64);
+
+/// Returns the help text for the instance domain internally created by
+/// the metric wrapper `kind`, truncated to fit a string block so that a
+/// long metric name can't make it invalid
+fn indom_helptext(kind: &str, name: &str) -> String {
+    let mut helptext = format!("Instance domain for {} '{}'", kind, name);
+    let mut max_len = (STRING_BLOCK_LEN - 1) as usize;
+    if helptext.len() > max_len {
+        while !helptext.is_char_boundary(max_len) {
+            max_len -= 1;
+        }
+        helptext.truncate(max_len);
+    }
+    helptext
+}
+
+#[derive(Clone, Debug)]
 /// An instance domain is a set of instances
 pub struct Indom {
     instances: HashSet<String>,
@@ -613,18 +1112,44 @@ pub struct Indom {
 }
 
 impl Indom {
+    /// Maximum number of instances in an instance domain
+    ///
+    /// Every instance adds an instance block, and a value block for
+    /// every metric over the domain, to the MMV.
+    ///
+    /// Instance IDs are 32-bit hashes of the instance names, so even
+    /// below this limit two instances can get the same ID; the chance
+    /// is already around 40% for a domain this large, and about 0.1%
+    /// for one with 3,000 instances.
+    pub const MAX_INSTANCES: usize = 1 << 16;
+
     /// Creates a new instance domain
     ///
-    /// The result is an error if the length of any `instance`, `shorthelp`
-    /// or `longhelp` exceed 255 bytes.
+    /// The result is an error if there are more than `MAX_INSTANCES`
+    /// instances, if two distinct instances hash to the same instance
+    /// ID, or if the length of any `instance`, `shorthelp` or `longhelp`
+    /// exceed 255 bytes.
     pub fn new(instances: &[&str], shorthelp: &str, longhelp: &str) -> Result<Self, String> {
+        if instances.len() > Indom::MAX_INSTANCES {
+            return Err(format!("more than {} instances", Indom::MAX_INSTANCES));
+        }
+
         let mut hasher = DefaultHasher::new();
         instances.hash(&mut hasher);
 
+        let mut instance_ids = HashMap::with_capacity(instances.len());
         for instance in instances {
             if instance.len() >= STRING_BLOCK_LEN as usize {
                 return Err(format!("instance longer than {} bytes", STRING_BLOCK_LEN - 1));
             }
+            validate_name("instance", instance)?;
+
+            match instance_ids.insert(Indom::instance_id(instance), instance) {
+                Some(other) if other != instance => return Err(format!(
+                    "instances {} and {} have the same instance ID", other, instance
+                )),
+                _ => {}
+            }
         }
         if shorthelp.len() >= STRING_BLOCK_LEN as usize {
             return Err(format!("short help text longer than {} bytes", STRING_BLOCK_LEN - 1));
@@ -651,12 +1176,52 @@ impl Indom {
         self.instances.contains(instance)
     }
 
+    /// Checks if all given instances are in the domain
+    ///
+    /// The result is an error listing the instances that aren't, in
+    /// the order they were given.
+    pub fn contains_all(&self, instances: &[&str]) -> Result<(), Vec<String>> {
+        let missing: Vec<String> = instances.iter()
+            .filter(|instance| !self.has_instance(instance))
+            .map(|instance| instance.to_string())
+            .collect();
+
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(missing)
+        }
+    }
+
     /// Returns an iterator visiting the instances in
     /// arbitrary order
     pub fn instances_iter(&self) -> Iter<String> {
         self.instances.iter()
     }
 
+    /// Returns an iterator visiting the instance names in
+    /// arbitrary order
+    pub fn instances<'a>(&'a self) -> impl Iterator<Item=&'a str> {
+        self.instances.iter().map(|instance| instance.as_str())
+    }
+
+    /// Sets the short and long help texts of the instance domain
+    ///
+    /// The result is an error, and the help texts are left unchanged,
+    /// if the length of `shorthelp` or `longhelp` exceeds 255 bytes.
+    pub fn set_help(&mut self, shorthelp: &str, longhelp: &str) -> Result<(), String> {
+        if shorthelp.len() >= STRING_BLOCK_LEN as usize {
+            return Err(format!("short help text longer than {} bytes", STRING_BLOCK_LEN - 1));
+        }
+        if longhelp.len() >= STRING_BLOCK_LEN as usize {
+            return Err(format!("long help text longer than {} bytes", STRING_BLOCK_LEN - 1));
+        }
+        self.shorthelp = shorthelp.to_owned();
+        self.longhelp = longhelp.to_owned();
+        Ok(())
+    }
+
+    pub fn id(&self) -> u32 { self.id }
     pub fn shorthelp(&self) -> &str { &self.shorthelp }
     pub fn longhelp(&self) -> &str { &self.longhelp }
 
@@ -681,15 +1246,41 @@ struct Instance<T> {
 /// An instance metric is a set of related metrics with same
 /// type, semantics and unit. Many instance metrics can share
 /// the same set of instances, i.e., instance domain.
+///
+/// Every instance has it's own value block in the MMV, even if all
+/// instances hold the same value; the MMV format has no notion of
+/// instances sharing a value. Use `fill` to set all of them at once.
 pub struct InstanceMetric<T> {
-    indom: Indom,
+    indom: Arc<Indom>,
     vals: HashMap<String, Instance<T>>,
     metric: Metric<T>
 }
 
+impl<T: fmt::Debug> fmt::Debug for InstanceMetric<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let vals: BTreeMap<&String, &T> = self.vals.iter()
+            .map(|(instance, i)| (instance, &i.val))
+            .collect();
+
+        f.debug_struct("InstanceMetric")
+            .field("name", &self.metric.name)
+            .field("item", &self.metric.item)
+            .field("sem", &self.metric.sem)
+            .field("indom", &self.indom.id)
+            .field("unit", &Unit::from(self.metric.unit))
+            .field("shorthelp", &self.metric.shorthelp)
+            .field("longhelp", &self.metric.longhelp)
+            .field("vals", &vals)
+            .finish()
+    }
+}
+
 impl<T: MetricType + Clone> InstanceMetric<T> {
     /// Creates a new instance metric
     ///
+    /// The instance domain is copied into the metric; use `new_shared`
+    /// to share one copy between many metrics.
+    ///
     /// The result is an error if the length of `name`, `shorthelp`
     /// or `longhelp` exceed 255 bytes.
     pub fn new(
@@ -701,6 +1292,25 @@ impl<T: MetricType + Clone> InstanceMetric<T> {
         shorthelp: &str,
         longhelp: &str) -> Result<Self, String> {
 
+        InstanceMetric::new_shared(
+            Arc::new(indom.clone()), name, init_val, sem, unit, shorthelp, longhelp
+        )
+    }
+
+    /// Creates a new instance metric that shares the given instance
+    /// domain, instead of holding a copy of it
+    ///
+    /// The result is an error if the length of `name`, `shorthelp`
+    /// or `longhelp` exceed 255 bytes.
+    pub fn new_shared(
+        indom: Arc<Indom>,
+        name: &str,
+        init_val: T,
+        sem: Semantics,
+        unit: Unit,
+        shorthelp: &str,
+        longhelp: &str) -> Result<Self, String> {
+
         let mut vals = HashMap::with_capacity(indom.instances.len());
         for instance_str in &indom.instances {
             let instance = Instance {
@@ -716,18 +1326,60 @@ impl<T: MetricType + Clone> InstanceMetric<T> {
         metric.indom = indom.id;
         
         Ok(InstanceMetric {
-            indom: indom.clone(),
+            indom: indom,
             vals: vals,
             metric: metric
         })
     }
 
-    /// Returns the number of instances that're part of the metric
-    pub fn instance_count(&self) -> u32 {
-        self.vals.len() as u32
-    }
+    /// Creates a new instance metric with given pairs of an instance
+    /// and it's initial value
+    ///
+    /// The result is an error if the pairs don't name every instance
+    /// of the domain exactly once, or if the length of `name`,
+    /// `shorthelp` or `longhelp` exceed 255 bytes.
+    pub fn new_with_vals(
+        indom: &Indom,
+        name: &str,
+        init_vals: &[(&str, T)],
+        sem: Semantics,
+        unit: Unit,
+        shorthelp: &str,
+        longhelp: &str) -> Result<Self, String> {
 
-    /// Check if given instance is part of the metric
+        let mut vals = HashMap::with_capacity(init_vals.len());
+        for &(instance, ref init_val) in init_vals {
+            if !indom.has_instance(instance) {
+                return Err(format!("instance {} isn't in the instance domain", instance));
+            }
+            if vals.insert(instance, init_val.clone()).is_some() {
+                return Err(format!("instance {} has more than one initial value", instance));
+            }
+        }
+        if vals.len() != indom.instances.len() {
+            return Err(format!(
+                "{} instances have no initial value", indom.instances.len() - vals.len()
+            ));
+        }
+
+        // the metric's own value isn't exported for instance metrics
+        let mut im = InstanceMetric::new(
+            indom, name, T::sentinel(), sem, unit, shorthelp, longhelp
+        )?;
+        for (instance, init_val) in vals {
+            if let Some(i) = im.vals.get_mut(instance) {
+                i.val = init_val;
+            }
+        }
+        Ok(im)
+    }
+
+    /// Returns the number of instances that're part of the metric
+    pub fn instance_count(&self) -> u32 {
+        self.vals.len() as u32
+    }
+
+    /// Check if given instance is part of the metric
     pub fn has_instance(&self, instance: &str) -> bool {
         self.vals.contains_key(instance)
     }
@@ -737,6 +1389,19 @@ impl<T: MetricType + Clone> InstanceMetric<T> {
         self.vals.get(instance).map(|i| &i.val)
     }
 
+    /// Reads the current value of the given instance from the
+    /// memory-mapped MMV file. If the instance isn't found, returns
+    /// `None`.
+    ///
+    /// Like `Metric::read_mapped`, this reflects values written to the
+    /// MMV by other processes, and is meaningless if the metric isn't
+    /// exported.
+    pub fn read_mapped(&self, instance: &str) -> Option<io::Result<T>> {
+        self.vals.get(instance).map(|i|
+            T::read(&mut unsafe { i.mmap_view.as_slice() })
+        )
+    }
+
     /// Sets the value of the given instance. If the instance isn't
     /// found, returns `None`.
     pub fn set_val(&mut self, instance: &str, new_val: T) -> Option<io::Result<()>>  {
@@ -747,6 +1412,86 @@ impl<T: MetricType + Clone> InstanceMetric<T> {
         })
     }
 
+    /// Sets the value of the instance whose internal id, as written
+    /// to the MMV, is `internal_id`. If no instance has that id,
+    /// returns `None`.
+    pub fn set_val_by_id(&mut self, internal_id: i32, new_val: T) -> Option<io::Result<()>> {
+        let instance = self.vals.keys()
+            .find(|instance| Indom::instance_id(instance) as i32 == internal_id)
+            .cloned()?;
+        self.set_val(&instance, new_val)
+    }
+
+    /// Removes the given instance from the metric by writing the
+    /// "no value available" sentinel into it's value. If the instance
+    /// isn't found, returns `None`.
+    ///
+    /// The sentinel is the minimum value for signed integers and
+    /// points in time, the maximum value for unsigned integers and
+    /// durations, the maximum `U32` for booleans, `NaN` for floats and
+    /// an empty string for strings. pmdammv only honours it if the `SENTINEL` flag was set on the
+    /// exporting client; otherwise the sentinel is reported as a
+    /// regular value.
+    pub fn remove_instance(&mut self, instance: &str) -> Option<io::Result<()>> {
+        self.vals.remove(instance).map(|mut i|
+            T::write_sentinel(unsafe { &mut i.mmap_view.as_mut_slice() })
+        )
+    }
+
+    /// Sets the value of every instance to the one computed by `f`
+    /// from the instance and it's current value
+    ///
+    /// Instances are visited in no particular order. If writing a value
+    /// fails, the remaining instances are left unchanged.
+    pub fn update_all<F: FnMut(&str, T) -> T>(&mut self, mut f: F) -> io::Result<()> {
+        for (instance, i) in self.vals.iter_mut() {
+            let new_val = f(instance, i.val.clone());
+            new_val.write(unsafe { &mut i.mmap_view.as_mut_slice() })?;
+            i.val = new_val;
+        }
+        Ok(())
+    }
+
+    /// Returns a batch in which new values for many instances can be
+    /// buffered, and then written at once by committing it
+    ///
+    /// The buffered values aren't visible until the batch is committed
+    /// or dropped.
+    pub fn batch(&mut self) -> BatchUpdate<T> {
+        BatchUpdate::new(self)
+    }
+
+    /// Sets the value of every instance to `val`
+    ///
+    /// The value is encoded only once and then copied into each
+    /// instance's value slot. Before the metric is exported, this just
+    /// sets the values that the export writes. The result is an error,
+    /// and no instance is changed, if `val` can't be encoded (e.g, a
+    /// `String` value exceeding 255 bytes).
+    pub fn fill(&mut self, val: T) -> io::Result<()> {
+        let mut encoded = Vec::with_capacity(NUMERIC_VALUE_SIZE);
+        val.write(&mut encoded)?;
+
+        for i in self.vals.values_mut() {
+            let slot = unsafe { i.mmap_view.as_mut_slice() };
+            slot[..encoded.len()].copy_from_slice(&encoded);
+            i.val = val.clone();
+        }
+        Ok(())
+    }
+
+    /// Sets the short and long help texts of the metric's instance
+    /// domain
+    ///
+    /// If the instance domain is shared with other metrics, the metric
+    /// gets it's own copy of it. As metrics with the same instances
+    /// share a single instance domain in the MMV, it's exported with
+    /// the help texts of the first such metric. The result is an error
+    /// if the length of `shorthelp` or `longhelp` exceeds 255 bytes.
+    pub fn set_indom_help(&mut self, shorthelp: &str, longhelp: &str) -> Result<(), String> {
+        Arc::make_mut(&mut self.indom).set_help(shorthelp, longhelp)
+    }
+
     pub fn name(&self) -> &str { &self.metric.name }
     pub fn sem(&self) -> &Semantics { &self.metric.sem }
     pub fn unit(&self) -> u32 { self.metric.unit }
@@ -771,14 +1516,15 @@ impl<T: MetricType> Metric<T> {
         c.set_position(metric_blk_off);
 
         // name
+        let name = exported_name(ws, &self.name);
         match mmv_ver {
             Version::V1 => {
-                c.write_all(self.name.as_bytes())?;
+                c.write_all(name.as_bytes())?;
                 c.write_all(&[0])?;
                 c.set_position(metric_blk_off + MMV1_NAME_MAX_LEN);
             },
             Version::V2 => {
-                let name_off = write_mmv_string(ws, c, &self.name, false)?;
+                let name_off = write_mmv_string(ws, c, &name, false)?;
                 c.write_u64::<Endian>(name_off)?;
             }
         }
@@ -802,16 +1548,26 @@ impl<T: MetricType> Metric<T> {
         let long_help_off = write_mmv_string(ws, c, &self.longhelp, false)?;
         c.write_u64::<Endian>(long_help_off)?;
 
+        ws.exported_metrics.push(ExportedMetricInfo {
+            name: name,
+            item: self.item,
+            type_code: self.val.type_code(),
+            metric_blk_off: metric_blk_off,
+            value_blk_offs: Vec::new()
+        });
+
         if write_value_blk {
             let (value_offset, value_size) =
                 write_value_block(ws, c, &self.val, metric_blk_off, 0)?;
 
-            let mmap_view = unsafe {
-                ws.mmap_view.as_mut().unwrap().clone()
-            };
-            let (_, value_mmap_view, _) =
-                three_way_split(mmap_view, value_offset, value_size)?;
-            self.mmap_view = value_mmap_view;
+            if ws.remap_metrics {
+                let mmap_view = unsafe {
+                    ws.mmap_view.as_mut().unwrap().clone()
+                };
+                let (_, value_mmap_view, _) =
+                    three_way_split(mmap_view, value_offset, value_size)?;
+                self.mmap_view = value_mmap_view;
+            }
         }
 
         ws.metric_blk_idx += 1;
@@ -828,7 +1584,7 @@ impl<T: MetricType> MMVWriter for Metric<T> {
         Ok(())
     }
 
-    fn register(&self, ws: &mut MMVWriterState, mmv_ver: Version) {
+    fn register(&self, ws: &mut MMVWriterState, mmv_ver: Version) -> io::Result<()> {
         ws.n_metrics += 1;
         ws.n_values += 1;
 
@@ -838,11 +1594,7 @@ impl<T: MetricType> MMVWriter for Metric<T> {
 
         cache_and_register_string(ws, &self.shorthelp);
         cache_and_register_string(ws, &self.longhelp);
-
-        match mmv_ver {
-            Version::V1 => {},
-            Version::V2 => cache_and_register_string(ws, &self.name)
-        }
+        register_name(ws, &self.name, mmv_ver)
     }
 
     fn has_mmv2_string(&self) -> bool {
@@ -868,19 +1620,20 @@ impl<T: MetricType> MMVWriter for InstanceMetric<T> {
                 write_value_block(ws, c, &instance.val, metric_blk_off, instance_blk_off)?;
 
             // set mmap_view for instance
-            let mmap_view = unsafe {
-                ws.mmap_view.as_mut().unwrap().clone()
-            };
-            let (_, value_mmap_view, _) =
-                three_way_split(mmap_view, value_offset, value_size)?;
-            instance.mmap_view = value_mmap_view;
-            
+            if ws.remap_metrics {
+                let mmap_view = unsafe {
+                    ws.mmap_view.as_mut().unwrap().clone()
+                };
+                let (_, value_mmap_view, _) =
+                    three_way_split(mmap_view, value_offset, value_size)?;
+                instance.mmap_view = value_mmap_view;
+            }
         }
 
         Ok(())
     }
 
-    fn register(&self, ws: &mut MMVWriterState, mmv_ver: Version) {
+    fn register(&self, ws: &mut MMVWriterState, mmv_ver: Version) -> io::Result<()> {
         ws.n_metrics += 1;
         ws.n_values += self.vals.len() as u64;
 
@@ -890,24 +1643,10 @@ impl<T: MetricType> MMVWriter for InstanceMetric<T> {
 
         cache_and_register_string(ws, &self.metric.shorthelp);
         cache_and_register_string(ws, &self.metric.longhelp);
-        cache_and_register_string(ws, &self.indom.shorthelp);
-        cache_and_register_string(ws, &self.indom.longhelp);
-
-        if !ws.indom_cache.contains_key(&self.indom.id) {
-            ws.n_indoms += 1;
-            ws.n_instances += self.indom.instances.len() as u64;
-            ws.indom_cache.insert(self.indom.id, None);
-
-            match mmv_ver {
-                Version::V1 => {},
-                Version::V2 => {
-                    cache_and_register_string(ws, &self.metric.name);
-                    for instance in &self.indom.instances {
-                        cache_and_register_string(ws, instance);
-                    }
-                }
-            }
-        }
+        register_name(ws, &self.metric.name, mmv_ver)?;
+
+        register_indom(ws, &self.indom, mmv_ver)?;
+        Ok(())
     }
 
     fn has_mmv2_string(&self) -> bool {
@@ -1010,6 +1749,11 @@ fn write_value_block<T: MetricType>(ws: &mut MMVWriterState,
     ws.value_blk_idx += 1;
     c.set_position(value_blk_off);
 
+    // value blocks are always written right after their metric block
+    if let Some(info) = ws.exported_metrics.last_mut() {
+        info.value_blk_offs.push(value_blk_off);
+    }
+
     let (value_offset, value_size);
     if value.type_code() == MTCode::String as u32 {
         // numeric value
@@ -1021,10 +1765,14 @@ fn write_value_block<T: MetricType>(ws: &mut MMVWriterState,
         // and in order to not replicate the logic of write_mmv_string here,
         // we perform an extra write of the string to a temp buffer so we
         // can pass that to write_mmv_string.
-        let mut str_buf = [0u8; (STRING_BLOCK_LEN - 1) as usize];
+        // the buffer has room for the null terminator, which
+        // write_mmv_string adds by itself
+        let mut str_buf = [0u8; STRING_BLOCK_LEN as usize];
         value.write(&mut (&mut str_buf as &mut [u8]))?;
 
-        let str_val = unsafe { str::from_utf8_unchecked(&str_buf) };
+        let str_val = unsafe {
+            str::from_utf8_unchecked(&str_buf[..(STRING_BLOCK_LEN - 1) as usize])
+        };
         let string_val_off = write_mmv_string(ws, c, str_val, true)?;
         c.write_u64::<Endian>(string_val_off)?;
 
@@ -1048,6 +1796,69 @@ fn write_value_block<T: MetricType>(ws: &mut MMVWriterState,
     Ok((value_offset, value_size))
 }
 
+// registers the indom block, instance blocks and strings of `indom` if
+// it isn't registered yet, so metrics sharing an indom only reference
+// it by id
+//
+// the result is an error if a different set of instances was registered
+// under the same id, as only one of them could be written
+fn register_indom(ws: &mut MMVWriterState, indom: &Indom, mmv_ver: Version) -> io::Result<()> {
+    if let Some(instances) = ws.indom_instances.get(&indom.id) {
+        if *instances != indom.instances {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("indom id {} is shared by indoms with different instances", indom.id)
+            ));
+        }
+        return Ok(());
+    }
+
+    ws.n_indoms += 1;
+    ws.n_instances += indom.instances.len() as u64;
+    ws.indom_cache.insert(indom.id, None);
+    ws.indom_instances.insert(indom.id, indom.instances.clone());
+
+    cache_and_register_string(ws, &indom.shorthelp);
+    cache_and_register_string(ws, &indom.longhelp);
+    match mmv_ver {
+        Version::V1 => {},
+        Version::V2 => for instance in &indom.instances {
+            cache_and_register_string(ws, instance);
+        }
+    }
+    Ok(())
+}
+
+// returns `name` prefixed with the client's name prefix, if any
+fn exported_name(ws: &MMVWriterState, name: &str) -> String {
+    match ws.name_prefix {
+        Some(ref prefix) => format!("{}.{}", prefix, name),
+        None => name.to_owned()
+    }
+}
+
+// registers the exported name of a metric, which is an error if it's
+// too long for the MMV version
+fn register_name(ws: &mut MMVWriterState, name: &str, mmv_ver: Version) -> io::Result<()> {
+    let name = exported_name(ws, name);
+    let max_len = match mmv_ver {
+        Version::V1 => MMV1_NAME_MAX_LEN,
+        Version::V2 => STRING_BLOCK_LEN
+    };
+    if name.len() >= max_len as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("metric name {} is too long for MMV version {}", name, mmv_ver as u32)
+        ));
+    }
+
+    match mmv_ver {
+        Version::V1 => {},
+        Version::V2 => cache_and_register_string(ws, &name)
+    }
+    Ok(())
+}
+
 fn cache_and_register_string(ws: &mut MMVWriterState, string: &str) {
     if string.len() > 0 && !ws.non_value_string_cache.contains_key(string) {
         ws.non_value_string_cache.insert(string.to_owned(), None);
@@ -1139,6 +1950,58 @@ fn test_instance_metrics() {
     assert!(cache_sizes.set_val("L4", 16384).is_none());
 }
 
+#[test]
+fn test_indom_accessors() {
+    let instances = ["a", "b", "c"];
+    let indom = Indom::new(&instances, "", "").unwrap();
+
+    let mut hasher = DefaultHasher::new();
+    instances.hash(&mut hasher);
+    assert_eq!(indom.id(), (hasher.finish() as u32) & ((1 << INDOM_BIT_LEN) - 1));
+
+    let mut names: Vec<&str> = indom.instances().collect();
+    names.sort();
+    assert_eq!(names, instances);
+}
+
+#[test]
+fn test_indom_max_instances() {
+    let names: Vec<String> = (0..Indom::MAX_INSTANCES + 1).map(|n| format!("i{}", n)).collect();
+    let instances: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+
+    let indom = Indom::new(&instances[..Indom::MAX_INSTANCES], "", "").unwrap();
+    assert_eq!(indom.instance_count() as usize, Indom::MAX_INSTANCES);
+    assert!(Indom::new(&instances, "", "").is_err());
+}
+
+#[test]
+fn test_indom_instance_id_collision() {
+    // a birthday search finds two names with the same 32-bit ID quickly
+    let mut seen = HashMap::new();
+    let (a, b) = (0..).map(|n| format!("i{}", n))
+        .filter_map(|name| {
+            let id = Indom::instance_id(&name);
+            seen.insert(id, name.clone()).map(|other| (other, name))
+        })
+        .next().unwrap();
+
+    assert!(Indom::new(&[&a, "c"], "", "").is_ok());
+    assert!(Indom::new(&[&a, &b], "", "").is_err());
+    assert_eq!(Indom::new(&[&a, &a], "", "").unwrap().instance_count(), 1);
+}
+
+#[test]
+fn test_indom_contains_all() {
+    let indom = Indom::new(&["a", "b", "c"], "", "").unwrap();
+
+    assert_eq!(indom.contains_all(&[]), Ok(()));
+    assert_eq!(indom.contains_all(&["c", "a"]), Ok(()));
+    assert_eq!(
+        indom.contains_all(&["a", "x", "b", "y"]),
+        Err(vec!["x".to_owned(), "y".to_owned()])
+    );
+}
+
 #[test]
 fn test_units() {
     assert_eq!(Unit::new().pmapi_repr, 0);
@@ -1173,6 +2036,75 @@ fn test_units() {
 
     assert!(Unit::new().space(Space::Byte, 8).is_err());
     assert!(Unit::new().time(Time::Sec, -9).is_err());
+
+    let custom_count = Unit::new().count(Count::One, 1).unwrap()
+        .with_count_scale(3).unwrap();
+    assert_eq!(custom_count.raw_count_scale(), 3);
+    assert!(custom_count.count_scale().is_none());
+    assert_eq!(custom_count.count_dim(), 1);
+    assert_eq!(custom_count.pmapi_repr, 1 << 20 | 3 << 8);
+    assert!(Unit::new().with_count_scale(16).is_err());
+
+    let raw: u32 = unit.into();
+    assert_eq!(raw, unit.pmapi_repr());
+    assert_eq!(Unit::from(raw).pmapi_repr(), unit.pmapi_repr());
+
+    let decoded = Unit::from_raw(raw);
+    assert_eq!(decoded.space_dim(), space_dim);
+    assert_eq!(decoded.space_scale(), Some(Space::EByte));
+    assert_eq!(decoded.time_dim(), time_dim);
+    assert_eq!(decoded.time_scale(), Some(Time::Hour));
+    assert_eq!(decoded.count_dim(), count_dim);
+    assert_eq!(decoded.count_scale(), Some(Count::One));
+}
+
+#[test]
+fn test_unit_describe() {
+    let unit = Unit::new().space(Space::KByte, 1).unwrap();
+    assert_eq!(unit.describe(), "KiB");
+    assert!(!unit.describe().contains("0x"));
+
+    let rate = Unit::new()
+        .space(Space::KByte, 1).unwrap()
+        .time(Time::Sec, -1).unwrap();
+    assert_eq!(rate.describe(), "KiB / sec");
+
+    assert_eq!(Unit::new().describe(), "");
+}
+
+#[test]
+fn test_semantics_strings() {
+    for sem in &[Semantics::Counter, Semantics::Instant, Semantics::Discrete] {
+        let parsed: Semantics = sem.as_str().parse().unwrap();
+        assert_eq!(parsed as u32, *sem as u32);
+    }
+
+    assert_eq!(Semantics::Counter.as_str(), "counter");
+    assert_eq!(
+        "instant".parse::<Semantics>().unwrap() as u32,
+        Semantics::Instant as u32
+    );
+    assert!("gauge".parse::<Semantics>().is_err());
+}
+
+#[test]
+fn test_set_val_finite() {
+    let mut metric = Metric::new(
+        "finite", 0.0f64, Semantics::Instant, Unit::new(), "", ""
+    ).unwrap();
+
+    assert!(metric.set_val_finite(1.5).is_ok());
+    assert_eq!(*metric.val(), 1.5);
+
+    assert!(metric.set_val_finite(::std::f64::NAN).is_err());
+    assert!(metric.set_val_finite(::std::f64::INFINITY).is_err());
+    assert_eq!(*metric.val(), 1.5);
+
+    let mut metric = Metric::new(
+        "finite32", 0.0f32, Semantics::Instant, Unit::new(), "", ""
+    ).unwrap();
+    assert!(metric.set_val_finite(::std::f32::NEG_INFINITY).is_err());
+    assert!(metric.set_val_finite(2.5).is_ok());
 }
 
 #[test]
@@ -1189,10 +2121,10 @@ fn test_invalid_strings() {
         &invalid_string, 0, sem, unit, "", ""
     ).is_err());
     assert!(Metric::new(
-        "", 0, sem, unit, &invalid_string, ""
+        "valid", 0, sem, unit, &invalid_string, ""
     ).is_err());
     assert!(Metric::new(
-        "", 0, sem, unit, "", &invalid_string
+        "valid", 0, sem, unit, "", &invalid_string
     ).is_err());
 
     assert!(Indom::new(
@@ -1210,13 +2142,96 @@ fn test_invalid_strings() {
         &indom, &invalid_string, 0, sem, unit, "", ""
     ).is_err());
     assert!(InstanceMetric::new(
-        &indom, "", 0, sem, unit, &invalid_string, ""
+        &indom, "valid", 0, sem, unit, &invalid_string, ""
     ).is_err());
     assert!(InstanceMetric::new(
-        &indom, "", 0, sem, unit, "", &invalid_string
+        &indom, "valid", 0, sem, unit, "", &invalid_string
     ).is_err());
 }
 
+#[test]
+fn test_item_for_name() {
+    let metric = Metric::new("cpu", 0u64, Semantics::Counter, Unit::new(), "", "").unwrap();
+    assert_eq!(Metric::item_for_name("cpu"), metric.item());
+    assert!(Metric::item_for_name("cpu") < 1 << ITEM_BIT_LEN);
+}
+
+#[test]
+fn test_val_by_ref() {
+    let metric = Metric::new(
+        "by_ref", String::from("kabylake"), Semantics::Discrete, Unit::new(), "", ""
+    ).unwrap();
+    assert_eq!(metric.val(), "kabylake");
+    assert_eq!(metric.val().as_ptr(), metric.val.as_ptr());
+
+    let indom = Indom::new(&["a"], "", "").unwrap();
+    let im = InstanceMetric::new(
+        &indom, "by_ref_instances", String::from("skylake"), Semantics::Discrete, Unit::new(), "", ""
+    ).unwrap();
+    assert_eq!(im.val("a").unwrap().as_ptr(), im.vals["a"].val.as_ptr());
+}
+
+#[test]
+fn test_reset() {
+    use super::Client;
+
+    let mut metric = Metric::new(
+        "resettable", 7i64, Semantics::Instant, Unit::new(), "", ""
+    ).unwrap();
+    Client::new("metric_reset_test").unwrap()
+        .export(&mut [&mut metric]).unwrap();
+
+    metric.set_val(-3).unwrap();
+    assert_eq!(*metric.val(), -3);
+
+    metric.reset().unwrap();
+    assert_eq!(*metric.val(), 7);
+    assert_eq!(metric.read_mapped().unwrap(), 7);
+}
+
+#[test]
+fn test_labels() {
+    let mut metric = Metric::new(
+        "labelled", 0u32, Semantics::Instant, Unit::new(), "", ""
+    ).unwrap();
+    assert!(metric.labels().is_empty());
+
+    metric.with_labels(&[("hostname", "db1"), ("tier", "backend")]).unwrap();
+    assert_eq!(metric.labels(), &[
+        ("hostname".to_owned(), "db1".to_owned()),
+        ("tier".to_owned(), "backend".to_owned())
+    ][..]);
+
+    assert!(metric.with_labels(&[("1st", "x")]).is_err());
+    assert!(metric.with_labels(&[("has space", "x")]).is_err());
+    assert!(metric.with_labels(&[("a", "x"), ("a", "y")]).is_err());
+    assert_eq!(metric.labels().len(), 2);
+
+    metric.with_labels(&[]).unwrap();
+    assert!(metric.labels().is_empty());
+}
+
+#[test]
+fn test_name_chars() {
+    let sem = Semantics::Instant;
+    let unit = Unit::new();
+
+    assert!(Metric::new("has space", 0, sem, unit, "", "").is_err());
+    assert!(Metric::new("has/slash", 0, sem, unit, "", "").is_err());
+    assert!(Metric::new("net.if.in_bytes", 0, sem, unit, "", "").is_ok());
+
+    assert!(Indom::new(&["eth0", "has space"], "", "").is_err());
+    assert!(Indom::new(&["eth0", "lo.1"], "", "").is_ok());
+
+    assert!(Metric::new("", 0, sem, unit, "", "").is_err());
+    assert!(Indom::new(&["eth0", ""], "", "").is_err());
+    let indom = Indom::new(&["eth0"], "", "").unwrap();
+    assert!(InstanceMetric::new(&indom, "", 0, sem, unit, "", "").is_err());
+
+    // help texts are free-form
+    assert!(Metric::new("helped", 0, sem, unit, "any / text", "").is_ok());
+}
+
 #[test]
 fn test_mmv2_string_check() {
     use rand::{thread_rng, Rng};
@@ -1427,3 +2442,495 @@ fn test_simple_metrics() {
     // TODO: after implementing mmvdump functionality, test the
     // bytes of the entier MMV file
 }
+
+#[test]
+fn test_read_mapped() {
+    use super::Client;
+
+    let mut photons = Metric::new(
+        "photons", 1u64, Semantics::Counter, Unit::new(), "", ""
+    ).unwrap();
+    let mut color = Metric::new(
+        "color", String::from("cyan"), Semantics::Discrete, Unit::new(), "", ""
+    ).unwrap();
+
+    Client::new("read_mapped").unwrap()
+        .export(&mut [&mut photons, &mut color]).unwrap();
+
+    assert_eq!(photons.read_mapped().unwrap(), 1);
+    assert_eq!(color.read_mapped().unwrap(), "cyan");
+
+    // simulate an external write to the MMV
+    {
+        let mut slice = unsafe { photons.mmap_view.as_mut_slice() };
+        slice.write_u64::<super::Endian>(42).unwrap();
+    }
+    {
+        let mut slice = unsafe { color.mmap_view.as_mut_slice() };
+        slice.write_all(b"magenta\0").unwrap();
+    }
+
+    assert_eq!(*photons.val(), 1);
+    assert_eq!(photons.read_mapped().unwrap(), 42);
+    assert_eq!(color.val(), "cyan");
+    assert_eq!(color.read_mapped().unwrap(), "magenta");
+}
+
+#[test]
+fn test_long_string_value() {
+    use super::Client;
+
+    let max_val: String = ::std::iter::repeat('x')
+        .take(STRING_BLOCK_LEN as usize - 1).collect();
+    let mut max = Metric::new(
+        "max", max_val.clone(), Semantics::Discrete, Unit::new(), "", ""
+    ).unwrap();
+    let mut motd = Metric::new(
+        "motd", String::from("hello"), Semantics::Discrete, Unit::new(), "", ""
+    ).unwrap();
+    Client::new("long_string_value").unwrap()
+        .export(&mut [&mut max, &mut motd]).unwrap();
+    assert_eq!(max.read_mapped().unwrap(), max_val);
+
+    let long_val: String = ::std::iter::repeat('x').take(300).collect();
+    let err = motd.set_val(long_val).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(motd.val(), "hello");
+    assert_eq!(motd.read_mapped().unwrap(), "hello");
+
+    motd.set_val(max_val.clone()).unwrap();
+    assert_eq!(motd.read_mapped().unwrap(), max_val);
+}
+
+#[test]
+fn test_remove_instance() {
+    use super::super::mmv::dump;
+    use super::{Client, SENTINEL};
+
+    let indom = Indom::new(&["a", "b"], "", "").unwrap();
+    let mut im = InstanceMetric::new(
+        &indom, "removable", 1u64, Semantics::Instant, Unit::new(), "", ""
+    ).unwrap();
+
+    let client = Client::new_custom("remove_instance", SENTINEL, 0).unwrap();
+    client.export(&mut [&mut im]).unwrap();
+
+    assert!(im.remove_instance("a").unwrap().is_ok());
+    assert!(!im.has_instance("a"));
+    assert!(im.has_instance("b"));
+    assert!(im.remove_instance("a").is_none());
+
+    let mmv = dump(client.mmv_path()).unwrap();
+    let values: Vec<u64> = mmv.value_blks().values().map(|v| v.value()).collect();
+    assert_eq!(values.len(), 2);
+    assert!(values.contains(&u64::MAX));
+    assert!(values.contains(&1));
+}
+
+#[test]
+fn test_remove_bool_instance() {
+    use super::super::mmv::dump;
+    use super::{Client, SENTINEL};
+
+    let indom = Indom::new(&["a", "b"], "", "").unwrap();
+    let mut im = InstanceMetric::new(
+        &indom, "removable_bool", true, Semantics::Discrete, Unit::new(), "", ""
+    ).unwrap();
+    im.set_val("b", false).unwrap().unwrap();
+
+    let client = Client::new_custom("remove_bool_instance", SENTINEL, 0).unwrap();
+    client.export(&mut [&mut im]).unwrap();
+
+    assert!(im.remove_instance("a").unwrap().is_ok());
+
+    // the sentinel doesn't read back as false
+    let mmv = dump(client.mmv_path()).unwrap();
+    let values: Vec<u64> = mmv.value_blks().values().map(|v| v.value()).collect();
+    assert_eq!(values.len(), 2);
+    assert!(values.contains(&(u32::MAX as u64)));
+    assert!(values.contains(&0));
+}
+
+#[test]
+fn test_remove_time_instance() {
+    use std::time::UNIX_EPOCH;
+    use super::super::mmv::dump;
+    use super::{Client, SENTINEL};
+
+    let indom = Indom::new(&["a", "b"], "", "").unwrap();
+    let mut im = InstanceMetric::new(
+        &indom, "removable_time", UNIX_EPOCH, Semantics::Discrete, Unit::new(), "", ""
+    ).unwrap();
+
+    let client = Client::new_custom("remove_time_instance", SENTINEL, 0).unwrap();
+    client.export(&mut [&mut im]).unwrap();
+
+    assert!(im.remove_instance("a").unwrap().is_ok());
+
+    // the sentinel isn't the epoch the other instance holds
+    let mmv = dump(client.mmv_path()).unwrap();
+    let values: Vec<u64> = mmv.value_blks().values().map(|v| v.value()).collect();
+    assert_eq!(values.len(), 2);
+    assert!(values.contains(&(i64::MIN as u64)));
+    assert!(values.contains(&0));
+}
+
+#[test]
+fn test_shared_indom() {
+    use super::super::mmv::dump;
+    use super::Client;
+
+    let indom = Arc::new(Indom::new(&["a", "b", "c"], "", "").unwrap());
+    let mut counts = InstanceMetric::new_shared(
+        indom.clone(), "shared_counts", 0u64, Semantics::Counter, Unit::new(), "", ""
+    ).unwrap();
+    let mut times = InstanceMetric::new_shared(
+        indom.clone(), "shared_times", 0.0, Semantics::Instant, Unit::new(), "", ""
+    ).unwrap();
+    assert!(Arc::ptr_eq(&counts.indom, &times.indom));
+    assert_eq!(Arc::strong_count(&indom), 3);
+
+    let client = Client::new("shared_indom").unwrap();
+    client.export(&mut [&mut counts, &mut times]).unwrap();
+
+    let mmv = dump(client.mmv_path()).unwrap();
+    assert_eq!(mmv.indom_blks().len(), 1);
+    assert_eq!(mmv.instance_blks().len(), 3);
+    assert_eq!(mmv.value_blks().len(), 6);
+}
+
+#[test]
+fn test_update_all() {
+    use super::super::mmv::dump;
+    use super::Client;
+
+    let indom = Indom::new(&["a", "b", "c"], "", "").unwrap();
+    let mut im = InstanceMetric::new(
+        &indom, "doubled", 1u64, Semantics::Instant, Unit::new(), "", ""
+    ).unwrap();
+    im.set_val("b", 2).unwrap().unwrap();
+    im.set_val("c", 3).unwrap().unwrap();
+
+    let client = Client::new("update_all").unwrap();
+    client.export(&mut [&mut im]).unwrap();
+
+    im.update_all(|_, val| val * 2).unwrap();
+    assert_eq!(im.val("a"), Some(&2));
+    assert_eq!(im.val("b"), Some(&4));
+    assert_eq!(im.val("c"), Some(&6));
+
+    let mmv = dump(client.mmv_path()).unwrap();
+    let mut values: Vec<u64> = mmv.value_blks().values().map(|v| v.value()).collect();
+    values.sort();
+    assert_eq!(values, vec![2, 4, 6]);
+}
+
+#[test]
+fn test_fill() {
+    use super::super::mmv::dump;
+    use super::Client;
+
+    let indom = Indom::new(&["a", "b", "c"], "", "").unwrap();
+    let mut im = InstanceMetric::new(
+        &indom, "filled", 1u64, Semantics::Instant, Unit::new(), "", ""
+    ).unwrap();
+    im.fill(7).unwrap();
+    for instance in indom.instances() {
+        assert_eq!(im.val(instance), Some(&7));
+    }
+
+    let client = Client::new("fill").unwrap();
+    client.export(&mut [&mut im]).unwrap();
+    let mmv = dump(client.mmv_path()).unwrap();
+    assert!(mmv.value_blks().values().all(|v| v.value() == 7));
+
+    im.fill(9).unwrap();
+    let mmv = dump(client.mmv_path()).unwrap();
+    assert!(mmv.value_blks().values().all(|v| v.value() == 9));
+
+    let mut names = InstanceMetric::new(
+        &indom, "filled_names", String::from("x"), Semantics::Discrete, Unit::new(), "", ""
+    ).unwrap();
+    assert!(names.fill("x".repeat(STRING_BLOCK_LEN as usize)).is_err());
+    assert_eq!(names.val("a").unwrap(), "x");
+    names.fill(String::from("same")).unwrap();
+    assert_eq!(names.val("c").unwrap(), "same");
+}
+
+#[test]
+fn test_on_change() {
+    use std::sync::{Arc, Mutex};
+
+    let mut metric = Metric::new(
+        "observed", 0u32, Semantics::Instant, Unit::new(), "", ""
+    ).unwrap();
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let seen_clone = seen.clone();
+    metric.on_change(Box::new(move |val| seen_clone.lock().unwrap().push(*val)));
+
+    metric.set_val(1).unwrap();
+    metric.set_val(2).unwrap();
+    assert_eq!(*seen.lock().unwrap(), vec![1, 2]);
+}
+
+#[test]
+fn test_set_val_by_id() {
+    use super::super::mmv::dump;
+    use super::Client;
+
+    let indom = Indom::new(&["a", "b"], "", "").unwrap();
+    let mut im = InstanceMetric::new(
+        &indom, "by_id", 0u32, Semantics::Instant, Unit::new(), "", ""
+    ).unwrap();
+
+    let client = Client::new("set_val_by_id").unwrap();
+    client.export(&mut [&mut im]).unwrap();
+
+    // look up the internal id written to the MMV for instance "b"
+    let mmv = dump(client.mmv_path()).unwrap();
+    let internal_id = mmv.instance_blks().values()
+        .find(|instance| mmv.resolve_string(instance.external_id()) == Some("b"))
+        .unwrap().internal_id();
+
+    im.set_val_by_id(internal_id, 5).unwrap().unwrap();
+    assert_eq!(im.val("b"), Some(&5));
+    assert_eq!(im.val("a"), Some(&0));
+
+    let unused_id = (0..).find(|id| *id != internal_id && *id != Indom::instance_id("a") as i32).unwrap();
+    assert!(im.set_val_by_id(unused_id, 1).is_none());
+}
+
+#[test]
+fn test_set_helptext() {
+    use super::super::mmv::dump;
+    use super::Client;
+
+    let mut metric = Metric::new(
+        "helptext", 0u32, Semantics::Instant, Unit::new(), "old short", ""
+    ).unwrap();
+
+    metric.set_shorthelp("new short").unwrap();
+    metric.set_longhelp("new long").unwrap();
+    assert_eq!(metric.shorthelp(), "new short");
+    assert_eq!(metric.longhelp(), "new long");
+
+    let too_long: String = ::std::iter::repeat('a')
+        .take(STRING_BLOCK_LEN as usize).collect();
+    assert!(metric.set_shorthelp(&too_long).is_err());
+    assert!(metric.set_longhelp(&too_long).is_err());
+
+    let client = Client::new("set_helptext").unwrap();
+    client.export(&mut [&mut metric]).unwrap();
+
+    let mmv = dump(client.mmv_path()).unwrap();
+    let strings: Vec<&str> = mmv.string_blks().values().map(|s| s.string()).collect();
+    assert!(strings.contains(&"new short"));
+    assert!(strings.contains(&"new long"));
+    assert!(!strings.contains(&"old short"));
+}
+
+#[test]
+fn test_debug() {
+    let metric = Metric::new("debug_metric", 42u32, Semantics::Counter, Unit::new(), "", "").unwrap();
+    let metric_debug = format!("{:?}", metric);
+    assert!(metric_debug.contains("\"debug_metric\""));
+    assert!(metric_debug.contains("Counter"));
+    assert!(metric_debug.contains("val: 42"));
+
+    let indom = Indom::new(&["a", "b"], "", "").unwrap();
+    let im = InstanceMetric::new(&indom, "debug_im", 7u64, Semantics::Instant, Unit::new(), "", "").unwrap();
+    let im_debug = format!("{:?}", im);
+    assert!(im_debug.contains("\"debug_im\""));
+    assert!(im_debug.contains("vals: {\"a\": 7, \"b\": 7}"));
+
+    let counter = Counter::new("debug_counter", 1, "", "").unwrap();
+    assert!(format!("{:?}", counter).contains("\"debug_counter\""));
+}
+
+#[test]
+fn test_instance_metric_with_vals() {
+    use super::Client;
+
+    let indom = Indom::new(&["tiny", "small", "large"], "", "").unwrap();
+    let mut sizes = InstanceMetric::new_with_vals(
+        &indom, "sizes", &[("small", 8u32), ("large", 64), ("tiny", 1)],
+        Semantics::Discrete, Unit::new(), "", ""
+    ).unwrap();
+    assert_eq!(sizes.val("tiny"), Some(&1));
+    assert_eq!(sizes.val("small"), Some(&8));
+    assert_eq!(sizes.val("large"), Some(&64));
+
+    Client::new("instance_metric_with_vals").unwrap()
+        .export(&mut [&mut sizes]).unwrap();
+    assert_eq!(sizes.read_mapped("small").unwrap().unwrap(), 8);
+    assert_eq!(sizes.read_mapped("large").unwrap().unwrap(), 64);
+
+    let new_with_vals = |vals: &[(&str, u32)]| InstanceMetric::new_with_vals(
+        &indom, "sizes", vals, Semantics::Discrete, Unit::new(), "", ""
+    );
+    assert!(new_with_vals(&[("tiny", 1), ("small", 8)]).is_err());
+    assert!(new_with_vals(&[("tiny", 1), ("small", 8), ("huge", 512)]).is_err());
+    assert!(new_with_vals(&[("tiny", 1), ("small", 8), ("small", 16)]).is_err());
+}
+
+#[test]
+fn test_register_shared_indom() {
+    use super::Client;
+
+    let indom = Arc::new(Indom::new(&["a", "b"], "Shared", "Shared by many metrics").unwrap());
+    let mut metrics: Vec<InstanceMetric<u32>> = (0..50).map(|n|
+        InstanceMetric::new_shared(
+            indom.clone(), &format!("shared_indom_{}", n), 0, Semantics::Instant, Unit::new(), "", ""
+        ).unwrap()
+    ).collect();
+
+    let mut ws = MMVWriterState::new();
+    for metric in &metrics {
+        metric.register(&mut ws, Version::V1).unwrap();
+    }
+    assert_eq!(ws.n_indoms, 1);
+    assert_eq!(ws.n_instances, 2);
+    assert_eq!(ws.n_metrics, 50);
+    assert_eq!(ws.n_strings, 2);
+
+    // every metric name, but the instance names only once
+    let mut ws = MMVWriterState::new();
+    for metric in &metrics {
+        metric.register(&mut ws, Version::V2).unwrap();
+    }
+    assert_eq!(ws.n_indoms, 1);
+    assert_eq!(ws.n_strings, 2 + 50 + 2);
+
+    let long_name = "m".repeat(MMV1_NAME_MAX_LEN as usize);
+    let mut long_named = InstanceMetric::new_shared(
+        indom.clone(), &long_name, 0u32, Semantics::Instant, Unit::new(), "", ""
+    ).unwrap();
+    let mut writers: Vec<&mut MMVWriter> = metrics.iter_mut().map(|m| m as &mut MMVWriter).collect();
+    writers.push(&mut long_named);
+    Client::new("shared_indom_v2").unwrap().export(&mut writers).unwrap();
+}
+
+#[test]
+fn test_time_metrics() {
+    use super::Client;
+
+    let mut elapsed = Metric::new_duration(
+        "elapsed", Duration::from_secs(0), Semantics::Instant, "", ""
+    ).unwrap();
+    let started_at = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    let mut started = Metric::new_timestamp("started", started_at, "", "").unwrap();
+    assert_eq!(elapsed.unit(), Unit::new().time(Time::NSec, 1).unwrap().pmapi_repr);
+    assert_eq!(*started.sem() as u32, Semantics::Discrete as u32);
+
+    Client::new("time_metrics").unwrap()
+        .export(&mut [&mut elapsed, &mut started]).unwrap();
+
+    elapsed.set_val(Duration::from_millis(1500)).unwrap();
+    let nanos = u64::read(&mut unsafe { elapsed.mmap_view.as_slice() }).unwrap();
+    assert_eq!(nanos, 1_500_000_000);
+    assert_eq!(elapsed.read_mapped().unwrap(), Duration::from_millis(1500));
+
+    let secs = i64::read(&mut unsafe { started.mmap_view.as_slice() }).unwrap();
+    assert_eq!(secs, 1_500_000_000);
+    started.set_val(UNIX_EPOCH - Duration::from_millis(2500)).unwrap();
+    assert_eq!(started.read_mapped().unwrap(), UNIX_EPOCH - Duration::from_secs(2));
+}
+
+#[test]
+fn test_indom_id_collision() {
+    use super::Client;
+
+    let indom = Indom::new(&["a", "b"], "", "").unwrap();
+    let mut metric = InstanceMetric::new(
+        &indom, "indom_id_collision", 0u32, Semantics::Instant, Unit::new(), "", ""
+    ).unwrap();
+
+    // a real collision can't be forced, so pretend another indom with
+    // the same id but different instances was registered first
+    let mut ws = MMVWriterState::new();
+    let mut other: HashSet<String> = HashSet::new();
+    other.insert("c".to_owned());
+    ws.indom_cache.insert(indom.id, None);
+    ws.indom_instances.insert(indom.id, other);
+    let err = metric.register(&mut ws, Version::V1).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(ws.n_indoms, 0);
+
+    let mut ws = MMVWriterState::new();
+    metric.register(&mut ws, Version::V1).unwrap();
+    metric.register(&mut ws, Version::V1).unwrap();
+    assert_eq!(ws.n_indoms, 1);
+
+    let mut colliding = Indom::new(&["c"], "", "").unwrap();
+    colliding.id = indom.id;
+    let mut same_id = InstanceMetric::new(
+        &colliding, "indom_id_collision2", 0u32, Semantics::Instant, Unit::new(), "", ""
+    ).unwrap();
+    assert!(Client::new("indom_id_collision").unwrap()
+        .export(&mut [&mut metric, &mut same_id]).is_err());
+}
+
+#[test]
+fn test_export_semantics() {
+    use super::Client;
+    use super::super::mmv;
+    use super::super::mmv::dump;
+
+    let mut discrete = Metric::new(
+        "discrete_u64", 7u64, Semantics::Discrete, Unit::new(), "", ""
+    ).unwrap();
+    let mut counter = Metric::new(
+        "counter_f64", 1.5f64, Semantics::Counter, Unit::new(), "", ""
+    ).unwrap();
+    let mut instant = Metric::new(
+        "instant_string", "up".to_owned(), Semantics::Instant, Unit::new(), "", ""
+    ).unwrap();
+    let indom = Indom::new(&["a", "b"], "", "").unwrap();
+    let mut discrete_instances = InstanceMetric::new(
+        &indom, "discrete_i32", -1i32, Semantics::Discrete, Unit::new(), "", ""
+    ).unwrap();
+
+    let client = Client::new("export_semantics").unwrap();
+    client.export(&mut [
+        &mut discrete, &mut counter, &mut instant, &mut discrete_instances
+    ]).unwrap();
+
+    let mmv = dump(client.mmv_path()).unwrap();
+    let sem_of = |name: &str| mmv.metric_blks().values()
+        .find(|blk| mmv.resolve_string(blk.name()) == Some(name))
+        .and_then(|blk| Semantics::from_u32(blk.sem()))
+        .map(|sem| sem.as_str());
+
+    assert_eq!(sem_of("discrete_u64"), Some("discrete"));
+    assert_eq!(sem_of("counter_f64"), Some("counter"));
+    assert_eq!(sem_of("instant_string"), Some("instant"));
+    assert_eq!(sem_of("discrete_i32"), Some("discrete"));
+    assert_eq!(mmv.value_of("discrete_u64", None), Some(mmv::TypedValue::U64(7)));
+}
+
+#[test]
+fn test_instance_read_mapped() {
+    use super::Client;
+
+    let indom = Indom::new(&["north", "south"], "", "").unwrap();
+    let mut winds = InstanceMetric::new(
+        &indom, "winds", 3u32, Semantics::Instant, Unit::new(), "", ""
+    ).unwrap();
+
+    Client::new("instance_read_mapped").unwrap()
+        .export(&mut [&mut winds]).unwrap();
+
+    assert_eq!(winds.read_mapped("north").unwrap().unwrap(), 3);
+    assert!(winds.read_mapped("east").is_none());
+
+    // simulate an external write to the MMV
+    {
+        let mut slice = unsafe { winds.vals.get_mut("south").unwrap().mmap_view.as_mut_slice() };
+        slice.write_u32::<super::Endian>(9).unwrap();
+    }
+
+    assert_eq!(winds.read_mapped("south").unwrap().unwrap(), 9);
+    assert_eq!(winds.val("south"), Some(&3));
+    assert_eq!(winds.read_mapped("north").unwrap().unwrap(), 3);
+}
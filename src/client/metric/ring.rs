@@ -0,0 +1,127 @@
+use super::*;
+
+/// A ring metric for the most recent samples of a value, in a bounded
+/// buffer whose oldest samples are overwritten by newer ones
+///
+/// Internally uses an `InstanceMetric<T>` with `Semantics::Instant`,
+/// having one instance per slot of the buffer named by it's index,
+/// i.e., `0` to `capacity - 1`
+#[derive(Debug)]
+pub struct RingMetric<T> {
+    im: InstanceMetric<T>,
+    indom: Indom,
+    slots: Vec<String>,
+    head: usize,
+    len: usize
+}
+
+impl<T: MetricType + Clone> RingMetric<T> {
+    /// Creates a new ring metric with given capacity, whose slots hold
+    /// the initial value until samples are pushed to them
+    ///
+    /// The result is an error if `capacity` is `0`.
+    pub fn new(name: &str, capacity: usize, init_val: T, unit: Unit,
+        shorthelp_text: &str, longhelp_text: &str) -> Result<Self, String> {
+
+        if capacity == 0 {
+            return Err("ring capacity must be non-zero".to_owned());
+        }
+
+        let slots: Vec<String> = (0..capacity).map(|i| i.to_string()).collect();
+        let slot_strs: Vec<&str> = slots.iter().map(|slot| slot.as_str()).collect();
+
+        let indom_helptext = indom_helptext("RingMetric", name);
+        let indom = Indom::new(&slot_strs, &indom_helptext, &indom_helptext)?;
+
+        let im = InstanceMetric::new(
+            &indom,
+            name,
+            init_val,
+            Semantics::Instant,
+            unit,
+            shorthelp_text,
+            longhelp_text
+        )?;
+
+        Ok(RingMetric {
+            im: im,
+            indom: indom,
+            slots: slots,
+            head: 0,
+            len: 0
+        })
+    }
+
+    /// Writes a sample to the next slot, overwriting the oldest
+    /// sample if the buffer is full
+    pub fn push(&mut self, val: T) -> io::Result<()> {
+        self.im.set_val(&self.slots[self.head], val).unwrap()?;
+        self.head = (self.head + 1) % self.slots.len();
+        if self.len < self.slots.len() {
+            self.len += 1;
+        }
+        Ok(())
+    }
+
+    /// Returns the samples pushed so far that haven't been overwritten,
+    /// oldest first
+    pub fn samples(&self) -> Vec<T> {
+        let capacity = self.slots.len();
+        let oldest = (self.head + capacity - self.len) % capacity;
+        (0..self.len)
+            .map(|i| self.im.val(&self.slots[(oldest + i) % capacity]).unwrap().clone())
+            .collect()
+    }
+
+    /// Maximum number of samples held
+    pub fn capacity(&self) -> usize { self.slots.len() }
+
+    /// Index of the slot the next sample is written to
+    pub fn head(&self) -> usize { self.head }
+
+    /// Internally created instance domain
+    pub fn indom(&self) -> &Indom { &self.indom }
+}
+
+impl<T: MetricType + Clone> MMVWriter for RingMetric<T> {
+    private_impl!{}
+
+    fn write(&mut self, ws: &mut MMVWriterState, c: &mut Cursor<&mut [u8]>, mmv_ver: Version) -> io::Result<()> {
+        self.im.write(ws, c, mmv_ver)
+    }
+
+    fn register(&self, ws: &mut MMVWriterState, mmv_ver: Version) -> io::Result<()> {
+        self.im.register(ws, mmv_ver)
+    }
+
+    fn has_mmv2_string(&self) -> bool {
+        self.im.has_mmv2_string()
+    }
+}
+
+#[test]
+pub fn test() {
+    use super::super::Client;
+
+    assert!(RingMetric::new("empty_ring", 0, 0.0, Unit::new(), "", "").is_err());
+
+    let capacity = 4;
+    let mut ring = RingMetric::new("ring", capacity, 0.0, Unit::new(), "", "").unwrap();
+    assert!(ring.samples().is_empty());
+
+    Client::new("ring_test").unwrap()
+        .export(&mut [&mut ring]).unwrap();
+
+    ring.push(1.0).unwrap();
+    ring.push(2.0).unwrap();
+    assert_eq!(ring.samples(), vec![1.0, 2.0]);
+
+    for val in 3..(capacity + 3) {
+        ring.push(val as f64).unwrap();
+    }
+    // 1.0 and 2.0 were overwritten
+    assert_eq!(ring.samples(), vec![3.0, 4.0, 5.0, 6.0]);
+    assert_eq!(ring.head(), 2);
+    assert_eq!(ring.im.val("0"), Some(&5.0));
+    assert_eq!(ring.im.val("1"), Some(&6.0));
+}
@@ -0,0 +1,77 @@
+use super::*;
+use super::super::Client;
+
+/// A set of metrics of possibly different types, owned by the set
+/// and exported together
+pub struct MetricSet {
+    metrics: Vec<Box<MMVWriter>>
+}
+
+impl MetricSet {
+    /// Creates a new empty set
+    pub fn new() -> Self {
+        MetricSet {
+            metrics: Vec::new()
+        }
+    }
+
+    /// Adds a metric to the set
+    pub fn add<M: MMVWriter + 'static>(&mut self, metric: M) -> &mut Self {
+        self.metrics.push(Box::new(metric));
+        self
+    }
+
+    /// Number of metrics in the set
+    pub fn len(&self) -> usize { self.metrics.len() }
+
+    /// Returns `true` if the set has no metrics
+    pub fn is_empty(&self) -> bool { self.metrics.is_empty() }
+
+    /// Exports all metrics in the set to the client's MMV file
+    pub fn export_with(&mut self, client: &Client) -> io::Result<()> {
+        client.export(&mut [self])
+    }
+}
+
+impl MMVWriter for MetricSet {
+    private_impl!{}
+
+    fn write(&mut self, ws: &mut MMVWriterState, c: &mut Cursor<&mut [u8]>, mmv_ver: Version) -> io::Result<()> {
+        for m in self.metrics.iter_mut() {
+            m.write(ws, c, mmv_ver)?;
+        }
+        Ok(())
+    }
+
+    fn register(&self, ws: &mut MMVWriterState, mmv_ver: Version) -> io::Result<()> {
+        for m in self.metrics.iter() {
+            m.register(ws, mmv_ver)?;
+        }
+        Ok(())
+    }
+
+    fn has_mmv2_string(&self) -> bool {
+        self.metrics.iter().any(|m| m.has_mmv2_string())
+    }
+}
+
+#[test]
+pub fn test() {
+    use super::super::super::mmv::dump;
+
+    let mut set = MetricSet::new();
+    assert!(set.is_empty());
+
+    set.add(Counter::new("set_counter", 1, "", "").unwrap())
+        .add(Gauge::new("set_gauge", 1.5, "", "").unwrap())
+        .add(Timer::new("set_timer", Time::MSec, "", "").unwrap())
+        .add(CountVector::new("set_count_vector", 0, &["a", "b"], "", "").unwrap());
+    assert_eq!(set.len(), 4);
+
+    let client = Client::new("metric_set_test").unwrap();
+    set.export_with(&client).unwrap();
+
+    let mmv = dump(client.mmv_path()).unwrap();
+    assert_eq!(mmv.metric_blks().len(), 4);
+    assert_eq!(mmv.value_blks().len(), 5);
+}
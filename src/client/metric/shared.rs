@@ -0,0 +1,140 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+use super::*;
+
+/// A metric that can be shared and updated across threads
+///
+/// Wraps the metric in an `Arc<Mutex<M>>`, so cloning a `SharedMetric`
+/// yields another handle to the same metric. Every update locks the
+/// metric, and so does exporting it with a `Client`.
+///
+/// All metrics and wrappers in this module are `Send`, hence any of
+/// them can be shared this way.
+#[derive(Debug)]
+pub struct SharedMetric<M> {
+    arc: Arc<Mutex<M>>
+}
+
+impl<M> Clone for SharedMetric<M> {
+    fn clone(&self) -> Self {
+        SharedMetric {
+            arc: self.arc.clone()
+        }
+    }
+}
+
+impl<M: MMVWriter> SharedMetric<M> {
+    /// Wraps the given metric
+    pub fn new(metric: M) -> Self {
+        SharedMetric {
+            arc: Arc::new(Mutex::new(metric))
+        }
+    }
+
+    /// Locks the metric, blocking until the lock is acquired
+    ///
+    /// A panic in another thread holding the lock doesn't poison it,
+    /// as every update leaves the metric in a valid state.
+    pub fn lock(&self) -> MutexGuard<M> {
+        self.arc.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Locks the metric and calls `f` with it
+    pub fn with<R, F: FnOnce(&mut M) -> R>(&self, f: F) -> R {
+        f(&mut self.lock())
+    }
+}
+
+impl SharedMetric<Counter> {
+    /// Returns the current value of the counter
+    pub fn val(&self) -> u64 { self.lock().val() }
+
+    /// Increments the counter by the given value
+    pub fn inc(&self, increment: u64) -> io::Result<()> {
+        self.lock().inc(increment)
+    }
+
+    /// Increments the counter by `+1`
+    pub fn up(&self) -> io::Result<()> { self.lock().up() }
+}
+
+impl SharedMetric<Gauge> {
+    /// Returns the current value of the gauge
+    pub fn val(&self) -> f64 { self.lock().val() }
+
+    /// Sets the value of the gauge
+    pub fn set(&self, val: f64) -> io::Result<()> {
+        self.lock().set(val)
+    }
+
+    /// Increments the gauge by the given value
+    pub fn inc(&self, increment: f64) -> io::Result<()> {
+        self.lock().inc(increment)
+    }
+
+    /// Decrements the gauge by the given value
+    pub fn dec(&self, decrement: f64) -> io::Result<()> {
+        self.lock().dec(decrement)
+    }
+}
+
+impl SharedMetric<CountVector> {
+    /// Returns the current count of the instance
+    pub fn val(&self, instance: &str) -> Option<u64> {
+        self.lock().val(instance)
+    }
+
+    /// Increments the count of the instance by the given value
+    pub fn inc(&self, instance: &str, increment: u64) -> Option<io::Result<()>> {
+        self.lock().inc(instance, increment)
+    }
+
+    /// Increments the count of the instance by `+1`
+    pub fn up(&self, instance: &str) -> Option<io::Result<()>> {
+        self.lock().up(instance)
+    }
+}
+
+impl<M: MMVWriter> MMVWriter for SharedMetric<M> {
+    private_impl!{}
+
+    fn write(&mut self, ws: &mut MMVWriterState, c: &mut Cursor<&mut [u8]>, mmv_ver: Version) -> io::Result<()> {
+        self.lock().write(ws, c, mmv_ver)
+    }
+
+    fn register(&self, ws: &mut MMVWriterState, mmv_ver: Version) -> io::Result<()> {
+        self.lock().register(ws, mmv_ver)
+    }
+
+    fn has_mmv2_string(&self) -> bool {
+        self.lock().has_mmv2_string()
+    }
+}
+
+#[test]
+pub fn test() {
+    use std::thread;
+    use super::super::Client;
+
+    let mut counter = SharedMetric::new(
+        Counter::new("shared_counter", 0, "", "").unwrap()
+    );
+
+    let client = Client::new("shared_metric_test").unwrap();
+    client.export(&mut [&mut counter]).unwrap();
+
+    let handles: Vec<_> = (0..2).map(|_| {
+        let counter = counter.clone();
+        thread::spawn(move || {
+            for _ in 0..1000 {
+                counter.up().unwrap();
+            }
+        })
+    }).collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert_eq!(counter.val(), 2000);
+    counter.inc(5).unwrap();
+    assert_eq!(counter.with(|c| c.val()), 2005);
+}
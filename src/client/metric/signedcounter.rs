@@ -0,0 +1,109 @@
+use super::*;
+
+/// A counter metric for a signed integer value that may also decrease,
+/// e.g, a delta from some baseline
+///
+/// Internally uses a `Metric<i64>` with `Semantics::Counter` and
+/// `Count::One` scale, and `1` count dimension
+///
+/// PCP tools that convert counters to rates (e.g, `pmval`) treat a
+/// decreasing value as a counter wrap, so rates computed over an
+/// interval in which the counter was decremented are meaningless.
+#[derive(Debug)]
+pub struct SignedCounter {
+    metric: Metric<i64>,
+    init_val: i64
+}
+
+impl SignedCounter {
+    /// Creates a new signed counter metric with given initial value
+    pub fn new(name: &str, init_val: i64, shorthelp_text: &str, longhelp_text: &str) -> Result<Self, String> {
+        let metric = Metric::new(
+            name,
+            init_val,
+            Semantics::Counter,
+            Unit::new().count(Count::One, 1)?,
+            shorthelp_text,
+            longhelp_text
+        )?;
+
+        Ok(SignedCounter {
+            metric: metric,
+            init_val: init_val
+        })
+    }
+
+    /// Returns the current value of the counter
+    pub fn val(&self) -> i64 {
+        *self.metric.val()
+    }
+
+    /// Increments the counter by the given value
+    pub fn inc(&mut self, increment: i64) -> io::Result<()> {
+        let val = *self.metric.val();
+        self.metric.set_val(val + increment)
+    }
+
+    /// Decrements the counter by the given value
+    pub fn dec(&mut self, decrement: i64) -> io::Result<()> {
+        self.inc(-decrement)
+    }
+
+    /// Increments the counter by `+1`
+    pub fn up(&mut self) -> io::Result<()> {
+        self.inc(1)
+    }
+
+    /// Decrements the counter by `1`
+    pub fn down(&mut self) -> io::Result<()> {
+        self.inc(-1)
+    }
+
+    /// Resets the counter to the initial value that was passed when
+    /// creating it
+    pub fn reset(&mut self) -> io::Result<()> {
+        self.metric.set_val(self.init_val)
+    }
+}
+
+impl MMVWriter for SignedCounter {
+    private_impl!{}
+
+    fn write(&mut self, ws: &mut MMVWriterState, c: &mut Cursor<&mut [u8]>, mmv_ver: Version) -> io::Result<()> {
+        self.metric.write(ws, c, mmv_ver)
+    }
+
+    fn register(&self, ws: &mut MMVWriterState, mmv_ver: Version) -> io::Result<()> {
+        self.metric.register(ws, mmv_ver)
+    }
+
+    fn has_mmv2_string(&self) -> bool {
+        self.metric.has_mmv2_string()
+    }
+}
+
+#[test]
+pub fn test() {
+    use super::super::Client;
+
+    let mut counter = SignedCounter::new("signed_counter", 1, "", "").unwrap();
+    assert_eq!(counter.val(), 1);
+
+    Client::new("signed_counter_test").unwrap()
+        .export(&mut [&mut counter]).unwrap();
+
+    counter.inc(4).unwrap();
+    assert_eq!(counter.val(), 5);
+
+    counter.dec(8).unwrap();
+    assert_eq!(counter.val(), -3);
+    assert_eq!(counter.metric.read_mapped().unwrap(), -3);
+
+    counter.up().unwrap();
+    counter.down().unwrap();
+    counter.down().unwrap();
+    assert_eq!(counter.val(), -4);
+
+    counter.reset().unwrap();
+    assert_eq!(counter.val(), 1);
+}
@@ -0,0 +1,143 @@
+use super::*;
+
+/// A summary metric that tracks the count, sum and mean of observed values
+///
+/// A lighter alternative to `Histogram` which doesn't allocate any
+/// buckets. Exports the `count`, `sum` and `mean` statistics to an MMV
+/// by using an `InstanceMetric<f64>` with `Semantics::Instant`.
+#[derive(Debug)]
+pub struct Summary {
+    im: InstanceMetric<f64>,
+    indom: Indom,
+    count: u64,
+    sum: f64
+}
+
+const COUNT_INST: &str = "count";
+const SUM_INST: &str = "sum";
+const MEAN_INST: &str = "mean";
+
+const SUMMARY_INSTANCES: &[&str] = &[COUNT_INST, SUM_INST, MEAN_INST];
+
+impl Summary {
+    /// Creates a new summary metric with no observations
+    pub fn new(name: &str, unit: Unit,
+        shorthelp_text: &str, longhelp_text: &str) -> Result<Self, String> {
+
+        let indom_helptext = indom_helptext("Summary", name);
+        let indom = Indom::new(SUMMARY_INSTANCES, &indom_helptext, &indom_helptext)?;
+
+        let im = InstanceMetric::new(
+            &indom,
+            name,
+            0.0,
+            Semantics::Instant,
+            unit,
+            shorthelp_text,
+            longhelp_text
+        )?;
+
+        Ok(Summary {
+            im: im,
+            indom: indom,
+            count: 0,
+            sum: 0.0
+        })
+    }
+
+    fn update_instances(&mut self) -> io::Result<()> {
+        let mean = self.mean();
+        let (count, sum) = (self.count as f64, self.sum);
+        self.set_instance(COUNT_INST, count)?;
+        self.set_instance(SUM_INST, sum)?;
+        self.set_instance(MEAN_INST, mean)
+    }
+
+    // the instances are always there, but a missing one should still
+    // surface as an error from `observe` rather than a panic
+    fn set_instance(&mut self, instance: &str, val: f64) -> io::Result<()> {
+        self.im.set_val(instance, val).unwrap_or_else(|| Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("summary instance {} not found", instance)
+        )))
+    }
+
+    /// Observes a value
+    pub fn observe(&mut self, val: f64) -> io::Result<()> {
+        self.count += 1;
+        self.sum += val;
+        self.update_instances()
+    }
+
+    /// Resets the count, sum and mean to zero
+    pub fn reset(&mut self) -> io::Result<()> {
+        self.count = 0;
+        self.sum = 0.0;
+        self.update_instances()
+    }
+
+    /// Number of values observed so far
+    pub fn count(&self) -> u64 { self.count }
+
+    /// Sum of observed values
+    pub fn sum(&self) -> f64 { self.sum }
+
+    /// Mean of observed values
+    ///
+    /// If no values are yet observed `0` is returned
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum / self.count as f64
+        }
+    }
+
+    /// Internally created instance domain
+    pub fn indom(&self) -> &Indom { &self.indom }
+}
+
+impl MMVWriter for Summary {
+    private_impl!{}
+
+    fn write(&mut self, ws: &mut MMVWriterState, c: &mut Cursor<&mut [u8]>, mmv_ver: Version) -> io::Result<()> {
+        self.im.write(ws, c, mmv_ver)
+    }
+
+    fn register(&self, ws: &mut MMVWriterState, mmv_ver: Version) -> io::Result<()> {
+        self.im.register(ws, mmv_ver)
+    }
+
+    fn has_mmv2_string(&self) -> bool {
+        self.im.has_mmv2_string()
+    }
+}
+
+#[test]
+pub fn test() {
+    use super::super::Client;
+
+    let mut summary = Summary::new("summary", Unit::new(), "", "").unwrap();
+    assert_eq!(summary.mean(), 0.0);
+
+    Client::new("summary_test").unwrap()
+        .export(&mut [&mut summary]).unwrap();
+
+    for val in &[1.5, 2.5, 4.0, 8.0] {
+        summary.observe(*val).unwrap();
+    }
+
+    assert_eq!(summary.count(), 4);
+    assert_eq!(summary.sum(), 16.0);
+    assert_eq!(summary.mean(), summary.sum() / summary.count() as f64);
+
+    assert_eq!(summary.im.val(COUNT_INST), Some(&4.0));
+    assert_eq!(summary.im.val(SUM_INST), Some(&16.0));
+    assert_eq!(summary.im.val(MEAN_INST), Some(&4.0));
+
+    summary.reset().unwrap();
+    assert_eq!(summary.count(), 0);
+    assert_eq!(summary.sum(), 0.0);
+    assert_eq!(summary.mean(), 0.0);
+    assert_eq!(summary.im.val(MEAN_INST), Some(&0.0));
+}
@@ -1,10 +1,12 @@
 use super::*;
+use std::time::Duration as StdDuration;
 use time;
 use time::Tm;
 
 /// A timer metric for tracking elapsed time
 ///
 /// Internally uses a `Metric<i64>` with `Semantics::Instant` and `1` time dimension
+#[derive(Debug)]
 pub struct Timer {
     metric: Metric<i64>,
     time_scale: Time,
@@ -61,25 +63,16 @@ impl Timer {
 
     /// Stops the timer, updates the internal metric, and
     /// returns the time elapsed since the last `start`. If
-    /// the timer was stopped too early or too late such that
-    /// the internal nanosecond, microsecond or millisecond value
-    /// over/under-flows, then elapsed time isn't updated.
+    /// the elapsed time doesn't fit an `i64` in the timer's
+    /// time scale (e.g, nanoseconds after ~292 years), it
+    /// saturates to `i64::MAX`, as does the cumulative time.
     pub fn stop(&mut self) -> Result<i64, Error> {
         match self.start_time {
             Some(start_time) => {
-                let duration = time::now() - start_time;
-
-                let elapsed = match self.time_scale {
-                    Time::NSec => duration.num_nanoseconds().unwrap_or(0),
-                    Time::USec => duration.num_microseconds().unwrap_or(0),
-                    Time::MSec => duration.num_microseconds().unwrap_or(0),
-                    Time::Sec => duration.num_seconds(),
-                    Time::Min => duration.num_minutes(),
-                    Time::Hour => duration.num_hours()
-                };
+                let elapsed = self.scaled(time::now() - start_time);
 
                 let val = *self.metric.val();
-                self.metric.set_val(val + elapsed)?;
+                self.metric.set_val(val.saturating_add(elapsed))?;
 
                 // we need to record the time elapsed even if stop()
                 // was called before a single unit of time_scale passed
@@ -93,11 +86,76 @@ impl Timer {
         }
     }
 
+    /// Updates the internal metric and returns the time elapsed
+    /// since the last `start` or `lap`, without stopping the timer.
+    /// Returns an error if the timer wasn't previously started.
+    pub fn lap(&mut self) -> Result<i64, Error> {
+        match self.start_time {
+            Some(start_time) => {
+                let now = time::now();
+                let elapsed = self.scaled(now - start_time);
+
+                let val = *self.metric.val();
+                self.metric.set_val(val.saturating_add(elapsed))?;
+
+                // as with stop(), don't lose time if lap() was called
+                // before a single unit of time_scale passed
+                if elapsed != 0 {
+                    self.start_time = Some(now);
+                }
+
+                Ok(elapsed)
+            },
+            None => Err(Error::TimerNotStarted)
+        }
+    }
+
+    /// Adds a duration measured elsewhere to the cumulative elapsed
+    /// time, converted to the timer's time scale like in `stop`
+    ///
+    /// The result is an error if the duration is too large to convert.
+    pub fn record(&mut self, duration: StdDuration) -> io::Result<()> {
+        let duration = time::Duration::from_std(duration).map_err(|_|
+            io::Error::new(io::ErrorKind::InvalidInput, "duration out of range")
+        )?;
+        let elapsed = self.scaled(duration);
+
+        let val = *self.metric.val();
+        self.metric.set_val(val.saturating_add(elapsed))
+    }
+
+    /// Resets the cumulative elapsed time to `0` and stops the
+    /// timer if it was started
+    pub fn reset(&mut self) -> io::Result<()> {
+        self.metric.set_val(0)?;
+        self.start_time = None;
+        Ok(())
+    }
+
     /// Returns the cumulative time elapsed between every
     /// `start` and `stop` pair.
     pub fn elapsed(&mut self) -> i64 {
         *self.metric.val()
     }
+
+    // the finer scales overflow for very long durations, which then
+    // saturate instead of being lost
+    fn scaled(&self, duration: time::Duration) -> i64 {
+        let saturated = if duration < time::Duration::zero() {
+            i64::min_value()
+        } else {
+            i64::max_value()
+        };
+
+        match self.time_scale {
+            Time::NSec => duration.num_nanoseconds().unwrap_or(saturated),
+            Time::USec => duration.num_microseconds().unwrap_or(saturated),
+            Time::MSec => duration.num_milliseconds(),
+            Time::Sec => duration.num_seconds(),
+            Time::Min => duration.num_minutes(),
+            Time::Hour => duration.num_hours()
+        }
+    }
 }
 
 impl MMVWriter for Timer {
@@ -107,7 +165,7 @@ impl MMVWriter for Timer {
         self.metric.write(ws, c, mmv_ver)
     }
 
-    fn register(&self, ws: &mut MMVWriterState, mmv_ver: Version) {
+    fn register(&self, ws: &mut MMVWriterState, mmv_ver: Version) -> io::Result<()> {
         self.metric.register(ws, mmv_ver)
     }
 
@@ -143,3 +201,72 @@ pub fn test() {
     let elapsed2 = timer.stop().unwrap();
     assert_eq!(timer.elapsed(), elapsed1 + elapsed2);
 }
+
+#[test]
+pub fn test_lap_and_reset() {
+    use super::super::Client;
+    use std::thread;
+    use std::time::Duration;
+
+    let mut timer = Timer::new("lap_timer", Time::MSec, "", "").unwrap();
+
+    Client::new("lap_timer_test").unwrap()
+        .export(&mut [&mut timer]).unwrap();
+
+    assert!(timer.lap().is_err());
+
+    timer.start().unwrap();
+    thread::sleep(Duration::from_millis(100));
+    let lap1 = timer.lap().unwrap();
+    assert!(lap1 > 0);
+    assert_eq!(timer.elapsed(), lap1);
+
+    thread::sleep(Duration::from_millis(100));
+    let lap2 = timer.lap().unwrap();
+    assert!(lap2 > 0);
+    assert_eq!(timer.elapsed(), lap1 + lap2);
+
+    timer.reset().unwrap();
+    assert_eq!(timer.elapsed(), 0);
+    assert!(timer.stop().is_err());
+}
+
+#[test]
+pub fn test_record() {
+    use super::super::Client;
+    use std::time::Duration;
+
+    let mut timer = Timer::new("record_timer", Time::Sec, "", "").unwrap();
+
+    Client::new("record_timer_test").unwrap()
+        .export(&mut [&mut timer]).unwrap();
+
+    timer.record(Duration::from_secs(2)).unwrap();
+    assert_eq!(timer.elapsed(), 2);
+    assert_eq!(timer.metric.read_mapped().unwrap(), 2);
+
+    let mut ms_timer = Timer::new("record_ms_timer", Time::MSec, "", "").unwrap();
+    ms_timer.record(Duration::from_millis(1500)).unwrap();
+    assert_eq!(ms_timer.elapsed(), 1500);
+
+    assert!(timer.record(Duration::from_secs(u64::max_value())).is_err());
+    assert_eq!(timer.elapsed(), 2);
+}
+
+#[test]
+pub fn test_saturation() {
+    let mut timer = Timer::new("saturating_timer", Time::NSec, "", "").unwrap();
+
+    // far more nanoseconds than an i64 holds
+    let huge = time::Duration::days(400 * 365);
+    assert_eq!(timer.scaled(huge), i64::max_value());
+    assert_eq!(timer.scaled(-huge), i64::min_value());
+
+    timer.time_scale = Time::USec;
+    assert_eq!(timer.scaled(huge), huge.num_microseconds().unwrap());
+
+    timer.time_scale = Time::NSec;
+    timer.record(StdDuration::from_secs(1)).unwrap();
+    timer.record(StdDuration::from_secs(400 * 365 * 24 * 60 * 60)).unwrap();
+    assert_eq!(timer.elapsed(), i64::max_value());
+}
@@ -0,0 +1,106 @@
+use time;
+use super::*;
+
+/// A metric paired with a companion `<name>.updated` metric holding
+/// the time, in seconds since the epoch, of it's last update
+///
+/// The companion is a `Metric<i64>` with `Semantics::Instant` and
+/// `Time::Sec` scale, and `1` time dimension. It holds the creation
+/// time of the wrapper until the first update.
+#[derive(Debug)]
+pub struct TimestampedMetric<T> {
+    metric: Metric<T>,
+    updated: Metric<i64>
+}
+
+impl<T: MetricType + Clone> TimestampedMetric<T> {
+    /// Wraps the given metric, creating it's companion metric
+    ///
+    /// The result is an error if the companion's name exceeds 255
+    /// bytes.
+    pub fn new(metric: Metric<T>) -> Result<Self, String> {
+        let updated = Metric::new(
+            &format!("{}.updated", metric.name()),
+            time::get_time().sec,
+            Semantics::Instant,
+            Unit::new().time(Time::Sec, 1)?,
+            &format!("Last update time of {}", metric.name()),
+            ""
+        )?;
+
+        Ok(TimestampedMetric {
+            metric: metric,
+            updated: updated
+        })
+    }
+
+    /// Returns the current value of the metric
+    pub fn val(&self) -> &T {
+        self.metric.val()
+    }
+
+    /// Sets the current value of the metric, and the companion metric
+    /// to the current time
+    pub fn set_val(&mut self, new_val: T) -> io::Result<()> {
+        self.metric.set_val(new_val)?;
+        self.updated.set_val(time::get_time().sec)
+    }
+
+    /// Returns the time of the last update in seconds since the epoch
+    pub fn updated(&self) -> i64 {
+        *self.updated.val()
+    }
+
+    /// Wrapped metric
+    pub fn metric(&self) -> &Metric<T> { &self.metric }
+}
+
+impl<T: MetricType + Clone> Metric<T> {
+    /// Wraps the metric in a `TimestampedMetric`, which also exports the
+    /// time of it's last update as `<name>.updated`
+    pub fn with_timestamp(self) -> Result<TimestampedMetric<T>, String> {
+        TimestampedMetric::new(self)
+    }
+}
+
+impl<T: MetricType + Clone> MMVWriter for TimestampedMetric<T> {
+    private_impl!{}
+
+    fn write(&mut self, ws: &mut MMVWriterState, c: &mut Cursor<&mut [u8]>, mmv_ver: Version) -> io::Result<()> {
+        self.metric.write(ws, c, mmv_ver)?;
+        self.updated.write(ws, c, mmv_ver)
+    }
+
+    fn register(&self, ws: &mut MMVWriterState, mmv_ver: Version) -> io::Result<()> {
+        self.metric.register(ws, mmv_ver)?;
+        self.updated.register(ws, mmv_ver)
+    }
+
+    fn has_mmv2_string(&self) -> bool {
+        self.metric.has_mmv2_string() || self.updated.has_mmv2_string()
+    }
+}
+
+#[test]
+pub fn test() {
+    use super::super::Client;
+
+    let metric = Metric::new(
+        "timestamped", 1u32, Semantics::Instant, Unit::new(), "", ""
+    ).unwrap();
+    let mut tm = metric.with_timestamp().unwrap();
+    assert_eq!(tm.updated.name(), "timestamped.updated");
+
+    let client = Client::new("timestamped_metric_test").unwrap();
+    client.export(&mut [&mut tm]).unwrap();
+    assert_eq!(client.exported_metrics().len(), 2);
+
+    tm.updated.set_val(0).unwrap();
+    let before = time::get_time().sec;
+    tm.set_val(2).unwrap();
+
+    assert_eq!(*tm.val(), 2);
+    assert!(tm.updated() >= before);
+    assert!(tm.updated.read_mapped().unwrap() >= before);
+    assert_eq!(tm.metric().read_mapped().unwrap(), 2);
+}
@@ -0,0 +1,46 @@
+//! Ready-made units for commonly exported quantities, to pass where
+//! a `Unit` is expected instead of building one dimension at a time
+
+use super::{Count, Space, Time, Unit};
+
+/// Bytes (`B`)
+pub fn bytes() -> Unit {
+    Unit::new().space(Space::Byte, 1).unwrap()
+}
+
+/// Bytes per second (`B / sec`)
+pub fn bytes_per_sec() -> Unit {
+    bytes().time(Time::Sec, -1).unwrap()
+}
+
+/// Seconds (`sec`)
+pub fn seconds() -> Unit {
+    Unit::new().time(Time::Sec, 1).unwrap()
+}
+
+/// Events per second (`/ sec`), e.g, for frequencies
+pub fn hertz() -> Unit {
+    Unit::new().time(Time::Sec, -1).unwrap()
+}
+
+/// Operations, or other counted events (`count`)
+pub fn ops() -> Unit {
+    Unit::new().count(Count::One, 1).unwrap()
+}
+
+/// Operations, or other counted events, per second (`count / sec`)
+pub fn ops_per_sec() -> Unit {
+    ops().time(Time::Sec, -1).unwrap()
+}
+
+#[test]
+fn test_units() {
+    assert_eq!(hertz(), Unit::new().time(Time::Sec, -1).unwrap());
+    assert_eq!(bytes(), Unit::new().space(Space::Byte, 1).unwrap());
+    assert_eq!(
+        bytes_per_sec(),
+        Unit::new().space(Space::Byte, 1).unwrap().time(Time::Sec, -1).unwrap()
+    );
+    assert_eq!(seconds().describe(), "sec");
+    assert_eq!(ops_per_sec().describe(), "count / sec");
+}
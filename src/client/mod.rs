@@ -1,6 +1,7 @@
 use byteorder::WriteBytesExt;
 use memmap::{Mmap, Protection};
 use regex::bytes::Regex;
+use std::collections::HashMap;
 use std::env;
 use std::ffi::{OsStr, OsString};
 use std::fmt;
@@ -11,8 +12,10 @@ use std::io::{BufReader, Cursor};
 use std::io::prelude::*;
 use std::path::{MAIN_SEPARATOR, Path, PathBuf};
 use std::str;
+use std::sync::{Mutex, MutexGuard};
 use time;
 
+use super::mmv;
 use super::mmv::Version;
 use super::{
     Endian,
@@ -32,7 +35,9 @@ pub mod metric;
 use self::metric::{MMVWriter, MMVWriterState};
 
 static PCP_TMP_DIR_KEY: &'static str = "PCP_TMP_DIR";
+static PCP_DIR_KEY: &'static str = "PCP_DIR";
 static MMV_DIR_SUFFIX: &'static str = "mmv";
+static LAYOUT_OVERFLOW_ERR: &'static str = "MMV layout overflows; too many blocks to export";
 
 #[cfg(unix)]
 fn get_process_id() -> i32 {
@@ -46,6 +51,17 @@ fn get_process_id() -> i32 {
     unsafe { kernel32::GetCurrentProcessId() as i32 }
 }
 
+#[cfg(unix)]
+fn set_file_mode(file: &File, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(fs::Permissions::from_mode(mode))
+}
+
+#[cfg(windows)]
+fn set_file_mode(_file: &File, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
 #[cfg(unix)]
 fn osstr_from_bytes(slice: &[u8]) -> &OsStr {
     use std::os::unix::ffi::OsStrExt;
@@ -57,28 +73,78 @@ fn osstr_from_bytes(slice: &[u8]) -> &OsStr {
     OsStr::new(unsafe { str::from_utf8_unchecked(slice) })
 }
 
+#[cfg(unix)]
+fn to_long_path(path: PathBuf) -> PathBuf {
+    path
+}
+
+// Windows limits paths to MAX_PATH (260) characters, and directories
+// to that less room for an 8.3 file name, unless the path is given in
+// the extended-length form; that form has to be absolute, and only
+// takes backslashes as separators
+#[cfg(windows)]
+fn to_long_path(path: PathBuf) -> PathBuf {
+    const MAX_DIR_PATH: usize = 248;
+
+    let long_path = match path.to_str() {
+        Some(path_str) if path_str.len() >= MAX_DIR_PATH
+            && path.is_absolute()
+            && !path_str.starts_with(r"\\?\") => {
+            let path_str = path_str.replace('/', "\\");
+            if path_str.starts_with(r"\\") {
+                format!(r"\\?\UNC\{}", &path_str[2..])
+            } else {
+                format!(r"\\?\{}", path_str)
+            }
+        },
+        _ => return path
+    };
+    PathBuf::from(long_path)
+}
+
 fn get_pcp_root() -> PathBuf {
-    match env::var_os("PCP_DIR") {
+    match env::var_os(PCP_DIR_KEY) {
         Some(val) => PathBuf::from(val),
         None => PathBuf::from(MAIN_SEPARATOR.to_string())
     }
 }
 
-fn init_pcp_conf(pcp_root: &Path) -> io::Result<()> {
-    /* attempt to load variables from pcp_root/etc/pcp.conf into environment.
-       if pcp_root/etc/pcp.conf is not a file, can't be read, or parsing it
-       fails, we *don't* return the error */
-    parse_pcp_conf(pcp_root.join("etc").join("pcp.conf")).ok();
+type PcpConf = HashMap<OsString, OsString>;
 
-    /* attempt to load variables from pcp_root/$PCP_CONF into environment.
-       if pcp_root/$PCP_CONF is not a file, can't be read, or parsing it
-       fails, we *do* return the error */
-    let pcp_conf = pcp_root
-        .join(env::var_os("PCP_CONF").unwrap_or(OsString::new()));
-    parse_pcp_conf(pcp_conf)
+fn init_pcp_conf(pcp_root: &Path) -> io::Result<PcpConf> {
+    /* attempt to load variables from pcp_root/etc/pcp.conf.
+       if pcp_root/etc/pcp.conf is not a file, can't be read, or parsing it
+       fails, the error is only returned if there's no other conf file */
+    let (mut conf, etc_err) = match parse_pcp_conf(pcp_root.join("etc").join("pcp.conf")) {
+        Ok(conf) => (conf, None),
+        Err(err) => (PcpConf::new(), Some(err))
+    };
+
+    /* attempt to load variables from pcp_root/$PCP_CONF, where $PCP_CONF
+       is looked up in the environment and then in etc/pcp.conf.
+       if $PCP_CONF isn't set, or pcp_root/$PCP_CONF is not a file, can't
+       be read, or parsing it fails, the variables from etc/pcp.conf are
+       kept, and the error is only returned if there are none */
+    let pcp_conf_key = OsStr::new("PCP_CONF");
+    let pcp_conf = env::var_os(pcp_conf_key)
+        .or(conf.get(pcp_conf_key).cloned());
+    let res = match pcp_conf {
+        Some(pcp_conf) => parse_pcp_conf(pcp_root.join(pcp_conf)),
+        None => Err(etc_err.unwrap_or(io::Error::new(
+            io::ErrorKind::NotFound, "PCP_CONF isn't set"
+        )))
+    };
+
+    match res {
+        Ok(extra) => conf.extend(extra),
+        Err(err) => if conf.is_empty() {
+            return Err(err);
+        }
+    }
+    Ok(conf)
 }
 
-fn parse_pcp_conf<P: AsRef<Path>>(conf_path: P) -> io::Result<()> {
+fn parse_pcp_conf<P: AsRef<Path>>(conf_path: P) -> io::Result<PcpConf> {
     let pcp_conf = File::open(conf_path)?;
     let mut buf_reader = BufReader::new(pcp_conf);
 
@@ -95,15 +161,16 @@ fn parse_pcp_conf<P: AsRef<Path>>(conf_path: P) -> io::Result<()> {
                 .unwrap();
     }
 
+    let mut conf = PcpConf::new();
     let mut line = Vec::new();
     while buf_reader.read_until(b'\n', &mut line)? > 0 {
         match RE.captures(&line) {
             Some(caps) => {
                 match (caps.get(1), caps.get(2)) {
-                    (Some(key), Some(val)) => env::set_var(
-                        osstr_from_bytes(key.as_bytes()), 
-                        osstr_from_bytes(val.as_bytes()), 
-                    ),
+                    (Some(key), Some(val)) => { conf.insert(
+                        osstr_from_bytes(key.as_bytes()).to_os_string(),
+                        osstr_from_bytes(val.as_bytes()).to_os_string()
+                    ); },
                     _ => {}
                 }
             }
@@ -112,43 +179,59 @@ fn parse_pcp_conf<P: AsRef<Path>>(conf_path: P) -> io::Result<()> {
         line.clear();
     }
 
-    Ok(())
+    Ok(conf)
+}
+
+/* resolves the MMV directory from the PCP root and the value of
+   PCP_TMP_DIR (if any), falling back to the OS temp dir. doesn't
+   touch the environment or the filesystem */
+fn resolve_mmv_dir(pcp_root: &Path, pcp_tmp_dir: Option<&OsStr>, os_tmp_dir: &Path) -> PathBuf {
+    let tmp_dir = match pcp_tmp_dir {
+        Some(val) => Path::new(val),
+        None => os_tmp_dir
+    };
+    pcp_root.join(tmp_dir).join(MMV_DIR_SUFFIX)
 }
 
 fn get_mmv_dir() -> io::Result<PathBuf> {
     let pcp_root = get_pcp_root();
-    let mut mmv_dir = pcp_root.clone();
 
-    mmv_dir.push(match env::var_os(PCP_TMP_DIR_KEY) {
-        Some(val) => PathBuf::from(val),
-        None => {
-
-            init_pcp_conf(&pcp_root).ok();
-
-            /* re-check if PCP_TMP_DIR is set after parsing (any) conf files
-               if not, default to OS-specific temp dir and set PCP_TMP_DIR
-               so we don't enter this block again */
-            match env::var_os(PCP_TMP_DIR_KEY) {
-                Some(val) => PathBuf::from(val),
-                None => {
-                    let os_tmp_dir = env::temp_dir();
-                    env::set_var(PCP_TMP_DIR_KEY, os_tmp_dir.as_os_str());
-                    os_tmp_dir
-                }
-            }
-        }
-    });
-
-    mmv_dir.push(MMV_DIR_SUFFIX);
-    fs::create_dir_all(&mmv_dir)?;
+    /* PCP_TMP_DIR is looked up in the environment first, and then in
+       the (any) conf files */
+    let pcp_tmp_dir = match env::var_os(PCP_TMP_DIR_KEY) {
+        Some(val) => Some(val),
+        None => init_pcp_conf(&pcp_root).ok()
+            .and_then(|mut conf| conf.remove(OsStr::new(PCP_TMP_DIR_KEY)))
+    };
+
+    let mmv_dir = to_long_path(resolve_mmv_dir(
+        &pcp_root,
+        pcp_tmp_dir.as_ref().map(|val| val.as_os_str()),
+        &env::temp_dir()
+    ));
+    create_mmv_dir(&mmv_dir)?;
 
     Ok(mmv_dir)
 }
 
+fn create_mmv_dir(mmv_dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(mmv_dir).map_err(|err| io::Error::new(
+        err.kind(),
+        format!(
+            "couldn't create MMV directory {}: {}; set {} (or {}) to a writable location",
+            mmv_dir.display(), err, PCP_TMP_DIR_KEY, PCP_DIR_KEY
+        )
+    ))
+}
+
 bitflags! {
     /// Flags used to modify how a client exports metrics
     pub struct MMVFlags: u32 {
         /// Metric names aren't prefixed with MMV filename
+        ///
+        /// Metric names are always written to the MMV as given; it's
+        /// pmdammv that exposes them as `mmv.<mmv filename>.<name>`,
+        /// or as `mmv.<name>` if this flag is set.
         const NOPREFIX = 1;
         /// PID check is needed
         const PROCESS  = 2;
@@ -159,14 +242,29 @@ bitflags! {
 
 impl fmt::Display for MMVFlags {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        RawMMVFlags(self.bits()).fmt(f)
+    }
+}
+
+/// Flags as read from an MMV header, which may have bits set that
+/// aren't known to `MMVFlags`
+///
+/// Displayed like `MMVFlags`, with the unknown bits listed as
+/// `unknown(0x..)` instead of being dropped.
+pub (crate) struct RawMMVFlags(pub u32);
+
+impl fmt::Display for RawMMVFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flags = MMVFlags::from_bits_truncate(self.0);
+        let unknown = self.0 & !MMVFlags::all().bits();
         let mut prev_flag = false;
 
-        if self.contains(NOPREFIX)  {
+        if flags.contains(NOPREFIX)  {
             write!(f, "no prefix")?;
             prev_flag = true;
         }
 
-        if self.contains(PROCESS)  {
+        if flags.contains(PROCESS)  {
             if prev_flag {
                 write!(f, ",")?;
             }
@@ -174,7 +272,7 @@ impl fmt::Display for MMVFlags {
             prev_flag = true;
         }
 
-        if self.contains(SENTINEL)  {
+        if flags.contains(SENTINEL)  {
             if prev_flag {
                 write!(f, ",")?;
             }
@@ -182,11 +280,82 @@ impl fmt::Display for MMVFlags {
             prev_flag = true;
         }
 
+        if unknown != 0 {
+            if prev_flag {
+                write!(f, ",")?;
+            }
+            write!(f, "unknown(0x{:x})", unknown)?;
+            prev_flag = true;
+        }
+
         if !prev_flag {
             write!(f, "(no flags)")?;
         }
 
-        write!(f, " (0x{:x})", self.bits())
+        write!(f, " (0x{:x})", self.0)
+    }
+}
+
+impl str::FromStr for MMVFlags {
+    type Err = String;
+
+    /// Parses a comma-separated list of flag names, like `"process,sentinel"`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut flags = MMVFlags::empty();
+
+        for token in s.split(',').map(|token| token.trim()) {
+            match token {
+                "noprefix" | "no prefix" => flags |= NOPREFIX,
+                "process" => flags |= PROCESS,
+                "sentinel" => flags |= SENTINEL,
+                "" => {},
+                _ => return Err(format!("Invalid flag '{}'", token))
+            }
+        }
+
+        Ok(flags)
+    }
+}
+
+/// Information about a metric written to an MMV by a `Client`
+#[derive(Clone, Debug)]
+pub struct ExportedMetricInfo {
+    name: String,
+    item: u32,
+    type_code: u32,
+    metric_blk_off: u64,
+    value_blk_offs: Vec<u64>
+}
+
+impl ExportedMetricInfo {
+    pub fn name(&self) -> &str { &self.name }
+    pub fn item(&self) -> u32 { self.item }
+    pub fn type_code(&self) -> u32 { self.type_code }
+    /// Offset of the metric block in the MMV
+    pub fn metric_blk_offset(&self) -> u64 { self.metric_blk_off }
+    /// Offsets of the metric's value blocks in the MMV; one for
+    /// singleton metrics and one per instance for instance metrics
+    pub fn value_blk_offsets(&self) -> &[u64] { &self.value_blk_offs }
+}
+
+/// Staged export of metrics gathered across calls, created by
+/// `Client::begin_export`
+pub struct ExportBuilder<'c, 'm> {
+    client: &'c Client,
+    metrics: Vec<&'m mut MMVWriter>
+}
+
+impl<'c, 'm> ExportBuilder<'c, 'm> {
+    /// Adds a metric to be exported
+    pub fn add(&mut self, metric: &'m mut MMVWriter) -> &mut Self {
+        self.metrics.push(metric);
+        self
+    }
+
+    /// Exports all the added metrics to the client's MMV file, in
+    /// the same way as `Client::export`
+    pub fn finish(mut self) -> io::Result<()> {
+        self.client.export(&mut self.metrics)
     }
 }
 
@@ -194,11 +363,22 @@ impl fmt::Display for MMVFlags {
 pub struct Client {
     flags: MMVFlags,
     cluster_id: u32,
-    mmv_path: PathBuf
+    pid: i32,
+    file_mode: Option<u32>,
+    generation: Option<i64>,
+    mmv_path: PathBuf,
+    name: String,
+    per_process: bool,
+    name_prefix: Option<String>,
+    exported_metrics: Mutex<Vec<ExportedMetricInfo>>
 }
 
 impl Client {
     /// Creates a new client with `PROCESS` flag and `0` cluster ID
+    ///
+    /// The MMV directory is created if it's absent. The result is an
+    /// error, naming the directory, if that fails, or if `name` isn't
+    /// a plain file name.
     pub fn new(name: &str) -> io::Result<Client> {
         Client::new_custom(name, PROCESS, 0)
     }
@@ -206,136 +386,365 @@ impl Client {
     /// Creates a new client with custom flags and cluster ID
     ///
     /// Note that only the 12 least significant bits of `cluster_id` will be
-    /// used.
+    /// used. The result is an error if `name` is empty, `.` or `..`, or
+    /// contains a path separator.
     pub fn new_custom(name: &str, flags: MMVFlags, cluster_id: u32)
     -> io::Result<Client> {
-        let mmv_path = get_mmv_dir()?.join(name);
+        validate_client_name(name)?;
+        let mmv_path = to_long_path(get_mmv_dir()?.join(name));
         let cluster_id = cluster_id & ((1 << CLUSTER_ID_BIT_LEN) - 1);
 
         Ok(Client {
             flags: flags,
             cluster_id: cluster_id,
-            mmv_path: mmv_path
+            pid: get_process_id(),
+            file_mode: None,
+            generation: None,
+            mmv_path: mmv_path,
+            name: name.to_owned(),
+            per_process: false,
+            name_prefix: None,
+            exported_metrics: Mutex::new(Vec::new())
         })
     }
+
+    /// Creates a new client like `new`, whose MMV file is named
+    /// `<name>-<pid>` after the current process
+    ///
+    /// Several running copies of the same program then export to
+    /// separate MMV files instead of overwriting each other's.
+    pub fn new_per_process(name: &str) -> io::Result<Client> {
+        validate_client_name(name)?;
+        let pid = get_process_id();
+        let mut client = Client::new(&per_process_file_name(name, pid))?;
+        client.name = name.to_owned();
+        client.per_process = true;
+        Ok(client)
+    }
+
+    /// Modifies and returns the client with given process ID, which
+    /// is written to the MMV header instead of the current process's
+    ///
+    /// Useful when exporting metrics on behalf of another process,
+    /// whose liveness pmdammv should check when the `PROCESS` flag
+    /// is set. For a client created with `new_per_process`, the MMV
+    /// file is renamed after the given process ID too.
+    pub fn with_pid(mut self, pid: i32) -> Self {
+        self.pid = pid;
+        if self.per_process {
+            self.mmv_path.set_file_name(per_process_file_name(&self.name, pid));
+        }
+        self
+    }
+
+    /// Modifies and returns the client with given generation number,
+    /// which is written to the MMV header instead of the current time
+    ///
+    /// Meant for tests that compare exported MMVs byte for byte. pmdammv
+    /// uses the generation to notice re-exports, so real clients should
+    /// leave it to the default.
+    pub fn with_generation(mut self, gen: i64) -> Self {
+        self.generation = Some(gen);
+        self
+    }
+
+    /// Modifies and returns the client with a prefix that's prepended,
+    /// followed by a `.`, to the name of every exported metric
+    ///
+    /// A metric named `requests` is then written to the MMV as
+    /// `<prefix>.requests`, and exposed by pmdammv as
+    /// `mmv.<mmv filename>.<prefix>.requests`, or as
+    /// `mmv.<prefix>.requests` if the `NOPREFIX` flag is set. The
+    /// result is an error if the prefix isn't a valid metric name.
+    pub fn with_name_prefix(mut self, prefix: &str) -> Result<Self, String> {
+        metric::validate_name("prefix", prefix)?;
+        if prefix.starts_with('.') || prefix.ends_with('.') {
+            return Err(format!("prefix {:?} starts or ends with '.'", prefix));
+        }
+        self.name_prefix = Some(prefix.to_owned());
+        Ok(self)
+    }
+
+    /// Returns the prefix of exported metric names, if any
+    pub fn name_prefix(&self) -> Option<&str> {
+        self.name_prefix.as_ref().map(|prefix| prefix.as_str())
+    }
+
+    /// Modifies and returns the client with given permission bits,
+    /// which are set on the MMV file after creating it
+    ///
+    /// Useful when pmdammv runs as a different user than the exporting
+    /// process, and the umask would leave the MMV unreadable to it.
+    /// This is a no-op on Windows.
+    pub fn with_file_mode(mut self, mode: u32) -> Self {
+        self.file_mode = Some(mode);
+        self
+    }
     
     /// Exports metrics to an MMV file at `mmv_path`
     ///
     /// If an MMV file is already present at `mmv_path`, it's overwritten
-    /// with the newer metrics.
+    /// with the newer metrics. If exporting fails midway, the incomplete
+    /// MMV file is removed. If the metrics can't be laid out (e.g, a
+    /// name is too long), an existing file is left untouched.
+    ///
+    /// If the metrics need more blocks than an MMV can address, the
+    /// result is an `InvalidInput` error with the message
+    /// "MMV layout overflows; too many blocks to export".
+    ///
+    /// Exporting again (e.g, to add a metric) re-points every passed
+    /// metric to the new MMV, with it's current value. Metrics exported
+    /// before but not passed again must not be updated afterwards, as
+    /// they still map the old layout of the (same) file.
+    ///
+    /// This blocks on file IO; see `export_blocking`.
     pub fn export(&self, metrics: &mut [&mut MMVWriter]) -> io::Result<()> {
-        let mut ws = MMVWriterState::new();
+        self.export_common(metrics, &self.mmv_path)
+    }
 
-        let mut mmv_ver = Version::V1;
-        for m in metrics.iter() {
-            if m.has_mmv2_string() {
-                mmv_ver = Version::V2;
-                break;
+    /// Same as `export`, named for call sites in asynchronous code
+    ///
+    /// Exporting creates, sizes and memory-maps the MMV file, which
+    /// blocks the calling thread, so it shouldn't run on an async
+    /// executor's threads. Instead, move the client and the metrics
+    /// (both of which are `Send`) into the runtime's blocking thread
+    /// pool, e.g, with Tokio's `spawn_blocking`. Exporting takes no
+    /// locks that outlive the call. Updating an exported metric only
+    /// writes to memory, and doesn't block.
+    pub fn export_blocking(&self, metrics: &mut [&mut MMVWriter]) -> io::Result<()> {
+        self.export(metrics)
+    }
+
+    /// Exports metrics like `export`, and then checks the written MMV
+    /// with `verify`
+    ///
+    /// Meant for tests and CI, as it re-reads the whole MMV.
+    pub fn export_verified(&self, metrics: &mut [&mut MMVWriter]) -> io::Result<()> {
+        self.export(metrics)?;
+        self.verify()
+    }
+
+    /// Checks that the MMV at `mmv_path` parses, and that it has a
+    /// metric block and the value blocks of every metric the client
+    /// last exported
+    ///
+    /// The result is an `InvalidData` error if it doesn't.
+    pub fn verify(&self) -> io::Result<()> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+        let mmv = mmv::dump(&self.mmv_path).map_err(|err| invalid(err.to_string()))?;
+
+        let exported_metrics = self.exported_metrics();
+        if mmv.metric_blks().len() != exported_metrics.len() {
+            return Err(invalid(format!(
+                "MMV has {} metrics, but {} were exported",
+                mmv.metric_blks().len(), exported_metrics.len()
+            )));
+        }
+        for metric in exported_metrics.iter() {
+            let is_intact = mmv.metric_blks().contains_key(&metric.metric_blk_offset())
+                && metric.value_blk_offsets().iter()
+                    .all(|offset| mmv.value_blks().contains_key(offset));
+            if !is_intact {
+                return Err(invalid(format!("blocks of metric {} are missing", metric.name())));
             }
         }
+        Ok(())
+    }
 
-        for m in metrics.iter() {
-            m.register(&mut ws, mmv_ver);
+    /// Returns a builder to which metrics owned by different parts of a
+    /// program can be added, before exporting them all at once with
+    /// `ExportBuilder::finish`
+    pub fn begin_export<'m>(&self) -> ExportBuilder<'_, 'm> {
+        ExportBuilder {
+            client: self,
+            metrics: Vec::new()
         }
+    }
 
-        if ws.n_metrics > 0 {
-            ws.n_toc += 2 /* Metric and Value TOC */;
-        }
+    /// Exports the metrics yielded by an iterator to an MMV file at
+    /// `mmv_path`
+    ///
+    /// Same as `export`, for when the metrics aren't readily available
+    /// as a slice, e.g, when chaining metrics from several collections.
+    pub fn export_iter<'a, I>(&self, metrics: I) -> io::Result<()>
+        where I: IntoIterator<Item = &'a mut MMVWriter> {
 
-        if ws.n_strings > 0 {
-            ws.n_toc += 1 /* String TOC */;
+        let mut metrics: Vec<&mut MMVWriter> = metrics.into_iter().collect();
+        self.export(&mut metrics)
+    }
+
+    /// Exports groups of metrics, each to its own MMV file, and returns
+    /// the clients of the files
+    ///
+    /// Every group is a name, cluster ID and the metrics to export, and
+    /// gets a client created with `new_custom` and the `PROCESS` flag.
+    /// Exporting stops at the first group that fails.
+    pub fn export_clusters(groups: &mut [(&str, u32, &mut [&mut MMVWriter])])
+    -> io::Result<Vec<Client>> {
+        let mut clients = Vec::with_capacity(groups.len());
+        for group in groups.iter_mut() {
+            let client = Client::new_custom(group.0, PROCESS, group.1)?;
+            client.export(group.2)?;
+            clients.push(client);
         }
+        Ok(clients)
+    }
 
-        if ws.n_indoms > 0 {
-            ws.n_toc += 2 /* Indom and Instance TOC */;
+    /// Exports metrics to a temporary MMV file next to `mmv_path`, and
+    /// then atomically renames it to `mmv_path`
+    ///
+    /// Unlike `export`, readers of `mmv_path` never observe a partially
+    /// written (or truncated) MMV; they either see the previous file or
+    /// the complete new one. The metric handles map the temporary file,
+    /// which after the rename *is* the file at `mmv_path`, so later
+    /// updates are visible there. The tradeoff is that the temporary
+    /// file and `mmv_path` must be on the same filesystem, and that on
+    /// platforms where a mapped file can't be replaced (Windows), this
+    /// fails if `mmv_path` is still mapped by a previous export.
+    pub fn export_atomic(&self, metrics: &mut [&mut MMVWriter]) -> io::Result<()> {
+        let mut tmp_name = self.mmv_path.file_name()
+            .unwrap_or(OsStr::new("")).to_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = self.mmv_path.with_file_name(tmp_name);
+
+        self.export_common(metrics, &tmp_path)?;
+        let res = fs::rename(&tmp_path, &self.mmv_path);
+        if res.is_err() {
+            self.discard_export(&tmp_path);
         }
+        res
+    }
+
+    /// Returns the size in bytes of the MMV file that exporting the
+    /// given metrics in the given MMV version would write, without
+    /// touching the filesystem
+    ///
+    /// `export` writes an MMV version 2 file if any metric or instance
+    /// name (including the name prefix), or string value, is too long
+    /// for version 1, and a version 1 file otherwise. The result is the
+    /// same `InvalidInput` error as for `export` if the MMV would be too
+    /// large to lay out.
+    pub fn planned_size(&self, metrics: &mut [&mut MMVWriter], ver: Version) -> io::Result<u64> {
+        plan_layout(metrics, &mut self.writer_state(), ver)
+    }
+
+    /// Writes the MMV that exporting the given metrics in the given MMV
+    /// version would produce to `writer`, without touching the
+    /// filesystem
+    ///
+    /// This is for inspection only (e.g, to test how metrics are
+    /// serialized). The metrics aren't pointed to what was written, so
+    /// metrics exported before keep updating their MMV file, and
+    /// `exported_metrics` is left as is.
+    pub fn export_to_writer<W: Write>(&self, metrics: &mut [&mut MMVWriter], writer: &mut W, ver: Version) -> io::Result<()> {
+        let mut ws = self.writer_state();
+        ws.remap_metrics = false;
+        let mmv_size = plan_layout(metrics, &mut ws, ver)?;
+
+        // anonymous maps are zero-filled, like the extended MMV file
+        ws.mmap_view = Some(
+            Mmap::anonymous(mmv_size as usize, Protection::ReadWrite)?.into_view_sync()
+        );
+        self.write_mmv(metrics, &mut ws, ver)?;
 
-        /*
-            MMV layout:
-
-            -- MMV Header
-            
-            -- Instance Domain TOC Block
-            -- Instances TOC Block
-            -- Metrics TOC Block
-            -- Values TOC Block
-            -- Strings TOC Block
-
-            -- Instance Domain section
-            -- Instances section
-            -- Metrics section
-            -- Values section
-            -- Strings section
-            
-            After writing, every metric is given ownership
-            of the respective memory-mapped slice that contains
-            the metric's value. This is to ensure that the metric
-            is *only* able to write to it's value's slice when updating
-            it's value.
-        */
-
-        let hdr_toc_len = HDR_LEN + TOC_BLOCK_LEN*ws.n_toc;
-
-        ws.indom_sec_off = hdr_toc_len;
-        ws.instance_sec_off =
-            ws.indom_sec_off
-            + INDOM_BLOCK_LEN*ws.n_indoms;
-        
-        let (instance_blk_len, metric_blk_len) = match mmv_ver {
-            Version::V1 => (INSTANCE_BLOCK_LEN_MMV1, METRIC_BLOCK_LEN_MMV1),
-            Version::V2 => (INSTANCE_BLOCK_LEN_MMV2, METRIC_BLOCK_LEN_MMV2)
-        };
-
-        ws.metric_sec_off =
-            ws.instance_sec_off
-            + instance_blk_len*ws.n_instances;
-        ws.value_sec_off =
-            ws.metric_sec_off
-            + metric_blk_len*ws.n_metrics;
-        ws.string_sec_off =
-            ws.value_sec_off
-            + VALUE_BLOCK_LEN*ws.n_values;
-
-        let mmv_size = (
-            ws.string_sec_off
-            + STRING_BLOCK_LEN*ws.n_strings
-        ) as usize;
-
-        let mut file = OpenOptions::new()
+        writer.write_all(unsafe { ws.mmap_view.as_ref().unwrap().as_slice() })
+    }
+
+    fn discard_export(&self, path: &Path) {
+        // best effort; the file may already be gone or still be
+        // mapped by metrics written before the failure
+        fs::remove_file(path).ok();
+        self.lock_exported_metrics().clear();
+    }
+
+    // an error before `path` is opened leaves it untouched, and one
+    // after it's truncated removes it
+    fn export_common(&self, metrics: &mut [&mut MMVWriter], path: &Path) -> io::Result<()> {
+        let mut ws = self.writer_state();
+
+        let mmv_ver = self.mmv_version(metrics);
+        let mmv_size = plan_layout(metrics, &mut ws, mmv_ver)?;
+
+        let file = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
             .truncate(true)
-            .open(&self.mmv_path)?;
+            .open(path)?;
 
-        file.write(&vec![0; mmv_size])?;
+        let res = self.write_file(metrics, ws, &file, mmv_size, mmv_ver);
+        if res.is_err() {
+            self.discard_export(path);
+        }
+        res
+    }
+
+    fn write_file(&self, metrics: &mut [&mut MMVWriter], mut ws: MMVWriterState,
+        file: &File, mmv_size: u64, mmv_ver: Version) -> io::Result<()> {
+
+        if let Some(mode) = self.file_mode {
+            set_file_mode(file, mode)?;
+        }
+
+        // extending the (truncated) file zero-fills it without having
+        // to allocate and write a buffer of it's size
+        file.set_len(mmv_size)?;
 
         ws.mmap_view = Some(
-            Mmap::open(&file, Protection::ReadWrite)?.into_view_sync()
+            Mmap::open(file, Protection::ReadWrite)?.into_view_sync()
         );
+        self.write_mmv(metrics, &mut ws, mmv_ver)?;
+
+        *self.lock_exported_metrics() = ws.exported_metrics;
+        Ok(())
+    }
+
+    fn writer_state(&self) -> MMVWriterState {
+        let mut ws = MMVWriterState::new();
+        ws.name_prefix = self.name_prefix.clone();
+        ws
+    }
 
+    fn mmv_version(&self, metrics: &[&mut MMVWriter]) -> Version {
+        if metrics.iter().any(|m| m.has_mmv2_string()) {
+            return Version::V2;
+        }
+
+        // names may only be too long for version 1 once prefixed, which
+        // registering them in version 1 checks
+        if self.name_prefix.is_some() {
+            let mut ws = self.writer_state();
+            if metrics.iter().any(|m| m.register(&mut ws, Version::V1).is_err()) {
+                return Version::V2;
+            }
+        }
+        Version::V1
+    }
+
+    // writes the MMV to `ws.mmap_view`, which must already be as large
+    // as the planned layout
+    fn write_mmv(&self, metrics: &mut [&mut MMVWriter], ws: &mut MMVWriterState, mmv_ver: Version) -> io::Result<()> {
         let mut mmap_view = unsafe { ws.mmap_view.as_mut().unwrap().clone() };
         let mut c = Cursor::new(unsafe { mmap_view.as_mut_slice() });
 
         ws.flags = self.flags.bits();
+        ws.pid = self.pid;
         ws.cluster_id = self.cluster_id;
-        write_mmv_header(&mut ws, &mut c, mmv_ver)?;
+        ws.gen = self.generation.unwrap_or_else(|| time::now().to_timespec().sec);
+        write_mmv_header(ws, &mut c, mmv_ver)?;
 
-        write_toc_block(1, ws.n_indoms as u32, ws.indom_sec_off, &mut c)?;
-        write_toc_block(2, ws.n_instances as u32, ws.instance_sec_off, &mut c)?;
-        write_toc_block(3, ws.n_metrics as u32, ws.metric_sec_off, &mut c)?;
-        write_toc_block(4, ws.n_values as u32, ws.value_sec_off, &mut c)?;
-        write_toc_block(5, ws.n_strings as u32, ws.string_sec_off, &mut c)?;
+        for &(sec, entries, sec_off) in &toc_sections(ws) {
+            write_toc_block(sec, entries as u32, sec_off, &mut c)?;
+        }
 
         for m in metrics.iter_mut() {
-            m.write(&mut ws, &mut c, mmv_ver)?;
+            m.write(ws, &mut c, mmv_ver)?;
         }
 
         // unlock header; has to be done last
         c.set_position(ws.gen2_off);
-        c.write_i64::<Endian>(ws.gen)?;
-        
-        Ok(())
+        c.write_i64::<Endian>(ws.gen)
     }
 
     /// Returns the cluster ID of the MMV file
@@ -343,10 +752,143 @@ impl Client {
         self.cluster_id
     }
 
+    /// Returns the process ID written to the MMV header
+    pub fn pid(&self) -> i32 {
+        self.pid
+    }
+
+    /// Returns information about the metrics written by the last
+    /// export
+    ///
+    /// A failed export that has already overwritten the MMV clears the
+    /// information, while one that fails before touching the MMV leaves
+    /// it as is.
+    pub fn exported_metrics(&self) -> Vec<ExportedMetricInfo> {
+        self.lock_exported_metrics().clone()
+    }
+
+    // the lock is only held to replace or copy the list, which leaves
+    // it valid, so a poisoned lock is recovered
+    fn lock_exported_metrics(&self) -> MutexGuard<Vec<ExportedMetricInfo>> {
+        self.exported_metrics.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
     /// Returns the absolute filesystem path of the MMV file
     pub fn mmv_path(&self) -> &Path {
         self.mmv_path.as_path()
     }
+
+    /// Returns the name the client was created with
+    ///
+    /// For a client created with `new_per_process`, this is the name
+    /// without the process ID suffix.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+fn validate_client_name(name: &str) -> io::Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(|c| c == '/' || c == '\\') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid client name {:?}, expected a plain file name", name)
+        ));
+    }
+    Ok(())
+}
+
+fn per_process_file_name(name: &str, pid: i32) -> String {
+    format!("{}-{}", name, pid)
+}
+
+/// Registers the metrics and computes the section offsets in `ws`,
+/// returning the size of the MMV
+fn plan_layout(metrics: &[&mut MMVWriter], ws: &mut MMVWriterState, mmv_ver: Version) -> io::Result<u64> {
+    for m in metrics.iter() {
+        m.register(ws, mmv_ver)?;
+    }
+
+    // sections don't always come in pairs, e.g, an indom with no
+    // instances has an indom block but no instance blocks
+    ws.n_toc = toc_sections(ws).len() as u64;
+
+    layout_sections(ws, mmv_ver)
+}
+
+/// Returns the code, number of entries and offset of every section that
+/// gets a TOC block, in the order the blocks are written; `n_toc` counts
+/// exactly those
+///
+/// A block is written for a section with entries, and for the values
+/// section whenever there are metrics, as readers expect a values TOC
+/// block next to the metrics one even if only instance metrics over
+/// empty indoms are exported.
+fn toc_sections(ws: &MMVWriterState) -> Vec<(u32, u64, u64)> {
+    let sections = [
+        (1, ws.n_indoms, ws.indom_sec_off),
+        (2, ws.n_instances, ws.instance_sec_off),
+        (3, ws.n_metrics, ws.metric_sec_off),
+        (4, ws.n_values, ws.value_sec_off),
+        (5, ws.n_strings, ws.string_sec_off)
+    ];
+    sections.iter()
+        .filter(|&&(sec, entries, _)| entries > 0 || (sec == 4 && ws.n_metrics > 0))
+        .cloned()
+        .collect()
+}
+
+/*
+    MMV layout:
+
+    -- MMV Header
+    
+    -- Instance Domain TOC Block
+    -- Instances TOC Block
+    -- Metrics TOC Block
+    -- Values TOC Block
+    -- Strings TOC Block
+
+    -- Instance Domain section
+    -- Instances section
+    -- Metrics section
+    -- Values section
+    -- Strings section
+
+    Every string block is STRING_BLOCK_LEN bytes, in V2 too. The
+    strings section isn't variable-length storage; pmdammv reads it
+    as an array of fixed-size blocks (mmv_disk_string_t) and checks
+    that every offset falls on one, so packing short strings would
+    produce MMVs it can't read.
+    
+    After writing, every metric is given ownership
+    of the respective memory-mapped slice that contains
+    the metric's value. This is to ensure that the metric
+    is *only* able to write to it's value's slice when updating
+    it's value.
+
+    Every offset is computed with checked arithmetic, as a huge
+    number of blocks would otherwise wrap around to a bogus (but
+    valid looking) layout.
+*/
+fn layout_sections(ws: &mut MMVWriterState, mmv_ver: Version) -> io::Result<u64> {
+    fn section_end(sec_off: u64, blk_len: u64, n_blks: u64) -> io::Result<u64> {
+        blk_len.checked_mul(n_blks)
+            .and_then(|sec_len| sec_off.checked_add(sec_len))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, LAYOUT_OVERFLOW_ERR))
+    }
+
+    let (instance_blk_len, metric_blk_len) = match mmv_ver {
+        Version::V1 => (INSTANCE_BLOCK_LEN_MMV1, METRIC_BLOCK_LEN_MMV1),
+        Version::V2 => (INSTANCE_BLOCK_LEN_MMV2, METRIC_BLOCK_LEN_MMV2)
+    };
+
+    ws.indom_sec_off = section_end(HDR_LEN, TOC_BLOCK_LEN, ws.n_toc)?;
+    ws.instance_sec_off = section_end(ws.indom_sec_off, INDOM_BLOCK_LEN, ws.n_indoms)?;
+    ws.metric_sec_off = section_end(ws.instance_sec_off, instance_blk_len, ws.n_instances)?;
+    ws.value_sec_off = section_end(ws.metric_sec_off, metric_blk_len, ws.n_metrics)?;
+    ws.string_sec_off = section_end(ws.value_sec_off, VALUE_BLOCK_LEN, ws.n_values)?;
+
+    section_end(ws.string_sec_off, STRING_BLOCK_LEN, ws.n_strings)
 }
 
 fn write_mmv_header(ws: &mut MMVWriterState, c: &mut Cursor<&mut [u8]>, mmv_ver: Version) -> io::Result<()> {    
@@ -360,7 +902,6 @@ fn write_mmv_header(ws: &mut MMVWriterState, c: &mut Cursor<&mut [u8]>, mmv_ver:
     }
 
     // generation1
-    ws.gen = time::now().to_timespec().sec;
     c.write_i64::<Endian>(ws.gen)?;
     // generation2
     ws.gen2_off = c.position();
@@ -370,21 +911,18 @@ fn write_mmv_header(ws: &mut MMVWriterState, c: &mut Cursor<&mut [u8]>, mmv_ver:
     // flags
     c.write_u32::<Endian>(ws.flags)?;
     // pid
-    c.write_i32::<Endian>(get_process_id())?;
+    c.write_i32::<Endian>(ws.pid)?;
     // cluster id
     c.write_u32::<Endian>(ws.cluster_id)
 }
 
 fn write_toc_block(sec: u32, entries: u32, sec_off: u64, c: &mut Cursor<&mut [u8]>) -> io::Result<()> {
-    if entries > 0 {
-        // section type
-        c.write_u32::<Endian>(sec)?;
-        // no. of entries
-        c.write_u32::<Endian>(entries)?;
-        // section offset
-        c.write_u64::<Endian>(sec_off)?;
-    }
-    Ok(())
+    // section type
+    c.write_u32::<Endian>(sec)?;
+    // no. of entries
+    c.write_u32::<Endian>(entries)?;
+    // section offset
+    c.write_u64::<Endian>(sec_off)
 }
 
 #[test]
@@ -429,17 +967,463 @@ fn test_mmv_header() {
     assert_eq!(client.cluster_id(), cursor.read_u32::<Endian>().unwrap());
 }
 
+#[test]
+fn test_custom_pid() {
+    use super::mmv::dump;
+    use self::metric::{Metric, Semantics, Unit};
+
+    let client = Client::new("custom_pid_test").unwrap();
+    assert_eq!(client.pid(), get_process_id());
+
+    let client = client.with_pid(4242);
+    assert_eq!(client.pid(), 4242);
+
+    let mut metric = Metric::new("pid", 1u32, Semantics::Instant, Unit::new(), "", "").unwrap();
+    client.export(&mut [&mut metric]).unwrap();
+
+    let mmv = dump(client.mmv_path()).unwrap();
+    assert_eq!(mmv.header().pid(), 4242);
+}
+
+#[test]
+fn test_exported_metrics() {
+    use super::mmv::dump;
+    use self::metric::{Indom, InstanceMetric, Metric, Semantics, Unit};
+
+    let mut metric = Metric::new("exported", 1u32, Semantics::Instant, Unit::new(), "", "").unwrap();
+    let indom = Indom::new(&["a", "b"], "", "").unwrap();
+    let mut im = InstanceMetric::new(
+        &indom, "exported_instances", 1u64, Semantics::Instant, Unit::new(), "", ""
+    ).unwrap();
+
+    let client = Client::new("exported_metrics_test").unwrap();
+    assert!(client.exported_metrics().is_empty());
+    client.export(&mut [&mut metric, &mut im]).unwrap();
+
+    let exported = client.exported_metrics();
+    assert_eq!(exported.len(), 2);
+    assert_eq!(exported[0].name(), "exported");
+    assert_eq!(exported[0].item(), metric.item());
+    assert_eq!(exported[0].type_code(), metric.type_code());
+    assert_eq!(exported[0].value_blk_offsets().len(), 1);
+    assert_eq!(exported[1].name(), "exported_instances");
+    assert_eq!(exported[1].value_blk_offsets().len(), 2);
+
+    let mmv = dump(client.mmv_path()).unwrap();
+    for info in exported.iter() {
+        let metric_blk = mmv.metric_blks().get(&info.metric_blk_offset()).unwrap();
+        assert_eq!(*metric_blk.item(), Some(info.item()));
+        for value_blk_off in info.value_blk_offsets() {
+            let value_blk = mmv.value_blks().get(value_blk_off).unwrap();
+            assert_eq!(*value_blk.metric_offset(), Some(info.metric_blk_offset()));
+        }
+    }
+}
+
+#[test]
+fn test_export_atomic() {
+    use super::mmv::{dump, LiveMMV};
+    use self::metric::{Metric, Semantics, Unit};
+
+    let mut metric = Metric::new("atomic", 1u32, Semantics::Instant, Unit::new(), "", "").unwrap();
+    let client = Client::new("export_atomic_test").unwrap();
+    client.export_atomic(&mut [&mut metric]).unwrap();
+
+    let mut tmp_name = client.mmv_path().file_name().unwrap().to_os_string();
+    tmp_name.push(".tmp");
+    assert!(!client.mmv_path().with_file_name(tmp_name).exists());
+
+    // a reader of the previous file keeps seeing it whole while re-exporting
+    let live = LiveMMV::open(client.mmv_path()).unwrap();
+    metric.set_val(2).unwrap();
+    client.export_atomic(&mut [&mut metric]).unwrap();
+    assert!(live.is_consistent());
+
+    // handles map the renamed file
+    metric.set_val(3).unwrap();
+    let mmv = dump(client.mmv_path()).unwrap();
+    assert_eq!(mmv.header().gen1, mmv.header().gen2);
+    let value_blk = mmv.value_blks().values().next().unwrap();
+    assert_eq!(value_blk.value(), 3);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_file_mode() {
+    use std::os::unix::fs::PermissionsExt;
+    use self::metric::{Metric, Semantics, Unit};
+
+    let mut metric = Metric::new("file_mode", 1u32, Semantics::Instant, Unit::new(), "", "").unwrap();
+    let client = Client::new("file_mode_test").unwrap().with_file_mode(0o644);
+    client.export(&mut [&mut metric]).unwrap();
+
+    let mode = fs::metadata(client.mmv_path()).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o644);
+}
+
+#[test]
+fn test_export_iter() {
+    use super::mmv::dump;
+    use self::metric::{Counter, Gauge, Metric, Semantics, Unit};
+    use std::iter;
+
+    let mut counters = vec![
+        Counter::new("iter_counter_a", 1, "", "").unwrap(),
+        Counter::new("iter_counter_b", 2, "", "").unwrap()
+    ];
+    let mut gauge = Gauge::new("iter_gauge", 1.5, "", "").unwrap();
+    let mut color = Metric::new(
+        "iter_color", String::from("cyan"), Semantics::Discrete, Unit::new(), "", ""
+    ).unwrap();
+
+    let client = Client::new("export_iter_test").unwrap();
+    client.export_iter(
+        counters.iter_mut().map(|c| c as &mut MMVWriter)
+            .chain(iter::once(&mut gauge as &mut MMVWriter))
+            .chain(iter::once(&mut color as &mut MMVWriter))
+    ).unwrap();
+
+    let mmv = dump(client.mmv_path()).unwrap();
+    assert_eq!(mmv.metric_blks().len(), 4);
+    assert_eq!(client.exported_metrics().len(), 4);
+
+    counters[1].up().unwrap();
+    assert_eq!(counters[1].val(), 3);
+}
+
+#[test]
+fn test_reexport() {
+    use super::mmv::{iter_metrics, TypedValue};
+    use self::metric::{Metric, Semantics, Unit};
+
+    let mut first = Metric::new(
+        "reexport_first", 1u32, Semantics::Instant, Unit::new(), "", ""
+    ).unwrap();
+    let mut second = Metric::new(
+        "reexport_second", 10u32, Semantics::Instant, Unit::new(), "", ""
+    ).unwrap();
+
+    let client = Client::new("reexport_test").unwrap();
+    client.export(&mut [&mut first]).unwrap();
+    first.set_val(2).unwrap();
+
+    client.export(&mut [&mut second, &mut first]).unwrap();
+    assert_eq!(first.read_mapped().unwrap(), 2);
+
+    first.set_val(3).unwrap();
+    second.set_val(20).unwrap();
+
+    let mut values: Vec<_> = iter_metrics(client.mmv_path()).unwrap()
+        .map(|res| res.unwrap()).collect();
+    values.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(values, vec![
+        ("reexport_first".to_owned(), TypedValue::U32(3)),
+        ("reexport_second".to_owned(), TypedValue::U32(20))
+    ]);
+}
+
+#[test]
+fn test_large_export() {
+    use super::mmv::dump;
+    use self::metric::{CountVector, Metric, Semantics, Unit};
+
+    let names: Vec<String> = (0..5000).map(|i| format!("inst{}", i)).collect();
+    let instances: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+    let mut cv = CountVector::new("large_count_vector", 7, &instances, "", "").unwrap();
+    let mut color = Metric::new(
+        "large_color", String::from("red"), Semantics::Discrete, Unit::new(), "", ""
+    ).unwrap();
+
+    let client = Client::new("large_export_test").unwrap();
+    let planned = client.planned_size(&mut [&mut cv, &mut color], Version::V1).unwrap();
+    client.export(&mut [&mut cv, &mut color]).unwrap();
+    assert_eq!(fs::metadata(client.mmv_path()).unwrap().len(), planned);
+
+    let mmv = dump(client.mmv_path()).unwrap();
+    assert_eq!(mmv.value_blks().len(), 5001);
+    assert_eq!(mmv.instance_blks().len(), 5000);
+    assert!(mmv.value_blks().values()
+        .filter(|v| v.string_offset().is_none())
+        .all(|v| v.value() == 7));
+
+    // regions that aren't written to are zero-filled
+    let mut bytes = Vec::new();
+    File::open(client.mmv_path()).unwrap().read_to_end(&mut bytes).unwrap();
+    let string_off = mmv.value_blks().values()
+        .filter_map(|v| *v.string_offset()).next().unwrap() as usize;
+    let string_blk = &bytes[string_off..string_off + STRING_BLOCK_LEN as usize];
+    assert_eq!(&string_blk[..4], b"red\0");
+    assert!(string_blk[4..].iter().all(|&b| b == 0));
+}
+
+#[test]
+fn test_layout_overflow() {
+    let mut ws = MMVWriterState::new();
+    ws.n_toc = 2;
+    ws.n_metrics = 1;
+    ws.n_values = 1;
+    assert!(layout_sections(&mut ws, Version::V1).is_ok());
+
+    let is_overflow = |err: io::Error| {
+        err.kind() == io::ErrorKind::InvalidInput && err.to_string() == LAYOUT_OVERFLOW_ERR
+    };
+
+    ws.n_values = u64::max_value() / VALUE_BLOCK_LEN;
+    assert!(is_overflow(layout_sections(&mut ws, Version::V1).unwrap_err()));
+
+    ws.n_values = 1;
+    ws.n_strings = u64::max_value();
+    assert!(is_overflow(layout_sections(&mut ws, Version::V2).unwrap_err()));
+}
+
+#[test]
+fn test_planned_size() {
+    use self::metric::{CountVector, Gauge, Metric, Semantics, Unit};
+
+    let mut gauge = Gauge::new("planned_gauge", 1.5, "short", "long").unwrap();
+    let mut cv = CountVector::new("planned_count_vector", 0, &["a", "b", "c"], "", "").unwrap();
+    let mut color = Metric::new(
+        "planned_color", String::from("cyan"), Semantics::Discrete, Unit::new(), "", ""
+    ).unwrap();
+
+    let client = Client::new("planned_size_test").unwrap();
+    let planned = client.planned_size(&mut [&mut gauge, &mut cv, &mut color], Version::V1).unwrap();
+
+    client.export(&mut [&mut gauge, &mut cv, &mut color]).unwrap();
+    assert_eq!(fs::metadata(client.mmv_path()).unwrap().len(), planned);
+
+    // version 2 stores names in string blocks
+    let v2 = client.planned_size(&mut [&mut gauge, &mut cv, &mut color], Version::V2).unwrap();
+    assert!(v2 > planned);
+}
+
+#[test]
+fn test_fixed_generation() {
+    use super::mmv::dump;
+    use self::metric::{Indom, InstanceMetric, Metric, Semantics, Unit};
+
+    let mut metric = Metric::new("fixed_gen", 1u32, Semantics::Instant, Unit::new(), "", "").unwrap();
+    let indom = Indom::new(&["a", "b", "c"], "", "").unwrap();
+    let mut im = InstanceMetric::new(
+        &indom, "fixed_gen_instances", 2u64, Semantics::Instant, Unit::new(), "", ""
+    ).unwrap();
+
+    let client = Client::new("fixed_generation_test").unwrap().with_generation(7);
+    let mut exported_bytes = Vec::new();
+    for _ in 0..2 {
+        client.export(&mut [&mut metric, &mut im]).unwrap();
+        let mut bytes = Vec::new();
+        File::open(client.mmv_path()).unwrap().read_to_end(&mut bytes).unwrap();
+        exported_bytes.push(bytes);
+    }
+    assert_eq!(exported_bytes[0], exported_bytes[1]);
+
+    let mmv = dump(client.mmv_path()).unwrap();
+    assert_eq!(mmv.header().gen1(), 7);
+    assert_eq!(mmv.header().gen2(), 7);
+}
+
+#[test]
+fn test_begin_export() {
+    use super::mmv::dump;
+    use self::metric::{Counter, Gauge};
+
+    struct Http { requests: Counter, errors: Counter }
+    struct System { load: Gauge }
+
+    impl Http {
+        fn add_metrics<'m>(&'m mut self, export: &mut ExportBuilder<'_, 'm>) {
+            export.add(&mut self.requests).add(&mut self.errors);
+        }
+    }
+
+    impl System {
+        fn add_metrics<'m>(&'m mut self, export: &mut ExportBuilder<'_, 'm>) {
+            export.add(&mut self.load);
+        }
+    }
+
+    let mut http = Http {
+        requests: Counter::new("staged_requests", 0, "", "").unwrap(),
+        errors: Counter::new("staged_errors", 0, "", "").unwrap()
+    };
+    let mut system = System {
+        load: Gauge::new("staged_load", 0.5, "", "").unwrap()
+    };
+
+    let client = Client::new("begin_export_test").unwrap();
+    let mut export = client.begin_export();
+    http.add_metrics(&mut export);
+    system.add_metrics(&mut export);
+    export.finish().unwrap();
+
+    let mmv = dump(client.mmv_path()).unwrap();
+    assert_eq!(mmv.metric_blks().len(), 3);
+    assert_eq!(mmv.value_blks().len(), 3);
+
+    http.requests.up().unwrap();
+    assert_eq!(http.requests.val(), 1);
+}
+
+#[test]
+fn test_flags_from_str() {
+    assert_eq!("process,sentinel".parse::<MMVFlags>().unwrap(), PROCESS | SENTINEL);
+    assert_eq!("noprefix".parse::<MMVFlags>().unwrap(), NOPREFIX);
+    assert_eq!("".parse::<MMVFlags>().unwrap(), MMVFlags::empty());
+    assert!("process,bogus".parse::<MMVFlags>().is_err());
+}
+
+#[test]
+fn test_failed_export_cleanup() {
+    use self::metric::{Metric, Semantics, Unit};
+
+    struct FailingWriter;
+
+    impl MMVWriter for FailingWriter {
+        private_impl!{}
+
+        fn write(&mut self, _: &mut MMVWriterState, _: &mut Cursor<&mut [u8]>, _: Version) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::Other, "injected failure"))
+        }
+
+        fn register(&self, _: &mut MMVWriterState, _: Version) -> io::Result<()> { Ok(()) }
+
+        fn has_mmv2_string(&self) -> bool { false }
+    }
+
+    struct InvalidWriter;
+
+    impl MMVWriter for InvalidWriter {
+        private_impl!{}
+
+        fn write(&mut self, _: &mut MMVWriterState, _: &mut Cursor<&mut [u8]>, _: Version) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn register(&self, _: &mut MMVWriterState, _: Version) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::InvalidInput, "injected failure"))
+        }
+
+        fn has_mmv2_string(&self) -> bool { false }
+    }
+
+    let mut metric = Metric::new("ok", 1u32, Semantics::Instant, Unit::new(), "", "").unwrap();
+    let client = Client::new("failed_export").unwrap();
+
+    client.export(&mut [&mut metric]).unwrap();
+    assert!(client.mmv_path().exists());
+
+    // a failure before the file is opened leaves the previous export live
+    assert!(client.export(&mut [&mut metric, &mut InvalidWriter]).is_err());
+    assert!(client.mmv_path().exists());
+    metric.set_val(2).unwrap();
+    let mmv = super::mmv::dump(client.mmv_path()).unwrap();
+    assert_eq!(mmv.value_of("ok", None), Some(super::mmv::TypedValue::U32(2)));
+    assert_eq!(client.exported_metrics().len(), 1);
+
+    assert!(client.export(&mut [&mut metric, &mut FailingWriter]).is_err());
+    assert!(!client.mmv_path().exists());
+}
+
+#[test]
+fn test_noprefix_names() {
+    use super::mmv::{dump, VersionSpecificString};
+    use self::metric::{Metric, Semantics, Unit};
+
+    let mut metric = Metric::new("prefix.test", 1u32, Semantics::Instant, Unit::new(), "", "").unwrap();
+
+    for flags in &[PROCESS, PROCESS | NOPREFIX] {
+        let client = Client::new_custom("noprefix_test", *flags, 0).unwrap();
+        client.export(&mut [&mut metric]).unwrap();
+
+        let mmv = dump(client.mmv_path()).unwrap();
+        let metric_blk = mmv.metric_blks().values().next().unwrap();
+        match *metric_blk.name() {
+            VersionSpecificString::String(ref name) => assert_eq!(name, "prefix.test"),
+            VersionSpecificString::Offset(_) => panic!("expected an MMV1 name")
+        }
+    }
+}
+
 #[test]
 fn test_mmv_dir() {
     let pcp_root = get_pcp_root();
     let mmv_dir = get_mmv_dir().unwrap();
-    let tmp_dir = PathBuf::from(
-        env::var_os(PCP_TMP_DIR_KEY)
-        .expect(&format!("{} not set", PCP_TMP_DIR_KEY))
-    );
 
     assert!(mmv_dir.is_dir());
-    assert_eq!(mmv_dir, pcp_root.join(tmp_dir).join(MMV_DIR_SUFFIX));
+    assert!(mmv_dir.starts_with(&pcp_root));
+    assert!(mmv_dir.ends_with(MMV_DIR_SUFFIX));
+}
+
+#[test]
+fn test_resolve_mmv_dir() {
+    let root = Path::new("/root");
+    let os_tmp_dir = Path::new("os_tmp");
+
+    assert_eq!(
+        resolve_mmv_dir(root, Some(OsStr::new("pcp_tmp")), os_tmp_dir),
+        Path::new("/root/pcp_tmp").join(MMV_DIR_SUFFIX)
+    );
+    assert_eq!(
+        resolve_mmv_dir(root, None, os_tmp_dir),
+        Path::new("/root/os_tmp").join(MMV_DIR_SUFFIX)
+    );
+}
+
+#[test]
+fn test_pcp_conf_without_override() {
+    // only meaningful if $PCP_CONF doesn't point elsewhere
+    if env::var_os("PCP_CONF").is_some() {
+        return;
+    }
+
+    let pcp_root = env::temp_dir().join("hornet_pcp_conf_test");
+    fs::create_dir_all(pcp_root.join("etc")).unwrap();
+    let mut conf_file = File::create(pcp_root.join("etc").join("pcp.conf")).unwrap();
+    conf_file.write_all(b"# no PCP_CONF here\nPCP_TMP_DIR=/var/lib/pcp/tmp\n").unwrap();
+
+    let conf = init_pcp_conf(&pcp_root).unwrap();
+    let pcp_tmp_dir = conf.get(OsStr::new(PCP_TMP_DIR_KEY)).unwrap();
+    assert_eq!(pcp_tmp_dir, OsStr::new("/var/lib/pcp/tmp"));
+    assert_eq!(
+        resolve_mmv_dir(&pcp_root, Some(pcp_tmp_dir), Path::new("os_tmp")),
+        Path::new("/var/lib/pcp/tmp").join(MMV_DIR_SUFFIX)
+    );
+
+    // a PCP_CONF that can't be read doesn't discard etc/pcp.conf
+    conf_file.write_all(b"PCP_CONF=/nonexistent/pcp.conf\n").unwrap();
+    let conf = init_pcp_conf(&pcp_root).unwrap();
+    assert!(conf.contains_key(OsStr::new(PCP_TMP_DIR_KEY)));
+
+    fs::remove_dir_all(&pcp_root).unwrap();
+    assert!(init_pcp_conf(&pcp_root).is_err());
+}
+
+#[test]
+fn test_unwritable_mmv_dir() {
+    let file_path = env::temp_dir().join("unwritable_mmv_dir_test");
+    File::create(&file_path).unwrap();
+
+    let mmv_dir = resolve_mmv_dir(Path::new("/"), Some(file_path.as_os_str()), &env::temp_dir());
+    let err = create_mmv_dir(&mmv_dir).unwrap_err();
+    let msg = err.to_string();
+    assert!(msg.contains(&mmv_dir.display().to_string()));
+    assert!(msg.contains(PCP_TMP_DIR_KEY));
+}
+
+#[test]
+fn test_mmv_dir_threads() {
+    use std::thread;
+
+    let handles: Vec<_> = (0..2).map(|_|
+        thread::spawn(|| get_mmv_dir().unwrap())
+    ).collect();
+    let dirs: Vec<_> = handles.into_iter()
+        .map(|handle| handle.join().unwrap())
+        .collect();
+
+    assert_eq!(dirs[0], dirs[1]);
+    assert_eq!(dirs[0], get_mmv_dir().unwrap());
 }
 
 #[test]
@@ -481,9 +1465,281 @@ fn test_init_pcp_conf() {
     );
 
     let pcp_root = get_pcp_root();
-    if init_pcp_conf(&pcp_root).is_ok() {
+    if let Ok(conf) = init_pcp_conf(&pcp_root) {
         for key in conf_keys.iter() {
-            env::var(key).expect(&format!("{} not set", key));
+            conf.get(OsStr::new(key)).expect(&format!("{} not set", key));
+        }
+    }
+}
+
+#[test]
+fn test_export_clusters() {
+    use super::mmv::dump;
+    use self::metric::{Counter, Gauge};
+
+    let mut requests = Counter::new("requests", 0, "", "").unwrap();
+    let mut load = Gauge::new("load", 0.5, "", "").unwrap();
+    let mut free = Gauge::new("free", 0.25, "", "").unwrap();
+
+    let clients = Client::export_clusters(&mut [
+        ("cluster_http_test", 1, &mut [&mut requests]),
+        ("cluster_system_test", 2, &mut [&mut load, &mut free])
+    ]).unwrap();
+    assert_eq!(clients.len(), 2);
+
+    let http = dump(clients[0].mmv_path()).unwrap();
+    assert_eq!(http.header().cluster_id(), 1);
+    assert_eq!(http.metric_blks().len(), 1);
+
+    let system = dump(clients[1].mmv_path()).unwrap();
+    assert_eq!(system.header().cluster_id(), 2);
+    assert_eq!(system.metric_blks().len(), 2);
+}
+
+#[test]
+fn test_toc_count() {
+    use super::mmv::{dump, read_header};
+    use self::metric::{Indom, InstanceMetric, Metric, Semantics, Unit};
+    use byteorder::ByteOrder;
+
+    let client = Client::new("toc_count_test").unwrap();
+
+    // metric and value sections only
+    let mut metric = Metric::new("toc_metric", 1u32, Semantics::Discrete, Unit::new(), "", "").unwrap();
+    client.export(&mut [&mut metric]).unwrap();
+    let mmv = dump(client.mmv_path()).unwrap();
+    assert_eq!(mmv.header().toc_count(), 2);
+    assert!(mmv.indom_toc().is_none() && mmv.string_toc().is_none());
+
+    // indom, instance, metric, value and string sections
+    let indom = Indom::new(&["a", "b"], "indom", "").unwrap();
+    let mut im = InstanceMetric::new(&indom, "toc_im", 1u32, Semantics::Discrete, Unit::new(), "", "").unwrap();
+    client.export(&mut [&mut metric, &mut im]).unwrap();
+    let mmv = dump(client.mmv_path()).unwrap();
+    assert_eq!(mmv.header().toc_count(), 5);
+    assert_eq!(mmv.instance_blks().len(), 2);
+
+    // an indom without instances has no instance or value blocks, but
+    // the values TOC block still follows the indom and metric ones
+    let empty_indom = Indom::new(&[], "", "").unwrap();
+    let mut empty_im = InstanceMetric::new(&empty_indom, "toc_empty_im", 1u32, Semantics::Discrete, Unit::new(), "", "").unwrap();
+    client.export_verified(&mut [&mut empty_im]).unwrap();
+    assert_eq!(read_header(client.mmv_path()).unwrap().toc_count(), 3);
+    let mmv = dump(client.mmv_path()).unwrap();
+    assert_eq!(mmv.metric_blks().len(), 1);
+    assert_eq!(mmv.value_toc().entries(), 0);
+
+    let mut bytes = Vec::new();
+    File::open(client.mmv_path()).unwrap().read_to_end(&mut bytes).unwrap();
+    let toc_code = |i: u64| {
+        let off = (HDR_LEN + i * TOC_BLOCK_LEN) as usize;
+        Endian::read_u32(&bytes[off..off + 4])
+    };
+    assert_eq!(toc_code(0), 1);
+    assert_eq!(toc_code(1), 3);
+    assert_eq!(toc_code(2), 4);
+}
+
+#[test]
+fn test_new_per_process() {
+    let client = Client::new_per_process("per_process_test").unwrap();
+    assert_eq!(client.pid(), get_process_id());
+    assert_eq!(
+        client.mmv_path().file_name().unwrap().to_str().unwrap(),
+        format!("per_process_test-{}", get_process_id())
+    );
+
+    let first = Client::new_per_process("per_process_test").unwrap().with_pid(1001);
+    let second = Client::new_per_process("per_process_test").unwrap().with_pid(1002);
+    assert_ne!(first.mmv_path(), second.mmv_path());
+    assert!(first.mmv_path().ends_with("per_process_test-1001"));
+    assert!(second.mmv_path().ends_with("per_process_test-1002"));
+    assert_eq!(first.mmv_path().parent(), client.mmv_path().parent());
+
+    first.export(&mut []).unwrap();
+    second.export(&mut []).unwrap();
+    assert!(first.mmv_path().exists() && second.mmv_path().exists());
+}
+
+#[test]
+fn test_export_to_writer() {
+    use super::mmv::{dump, dump_from_bytes, TypedValue};
+    use self::metric::{Counter, Indom, InstanceMetric, Semantics, Unit};
+
+    let client = Client::new("export_to_writer_test").unwrap().with_generation(7);
+    let mut counter = Counter::new("written", 3, "", "").unwrap();
+    let indom = Indom::new(&["x", "y"], "", "").unwrap();
+    let mut im = InstanceMetric::new(&indom, "written_instances", 5u64, Semantics::Instant, Unit::new(), "", "").unwrap();
+
+    let mut bytes = Vec::new();
+    client.export_to_writer(&mut [&mut counter, &mut im], &mut bytes, Version::V1).unwrap();
+    assert_eq!(
+        bytes.len() as u64,
+        client.planned_size(&mut [&mut counter, &mut im], Version::V1).unwrap()
+    );
+    assert!(client.exported_metrics().is_empty());
+
+    let mmv = dump_from_bytes(bytes, false).unwrap();
+    assert_eq!(mmv.header().gen1(), 7);
+    assert_eq!(mmv.header().gen2(), 7);
+    assert_eq!(mmv.metric_blks().len(), 2);
+    assert_eq!(mmv.value_blks().len(), 3);
+    assert_eq!(mmv.instance_blks().len(), 2);
+
+    // exported metrics keep updating their MMV file
+    client.export(&mut [&mut counter, &mut im]).unwrap();
+    client.export_to_writer(&mut [&mut counter, &mut im], &mut Vec::new(), Version::V2).unwrap();
+    assert_eq!(client.exported_metrics().len(), 2);
+    counter.up().unwrap();
+    im.set_val("x", 6).unwrap().unwrap();
+
+    let mmv = dump(client.mmv_path()).unwrap();
+    assert_eq!(mmv.value_of("written", None), Some(TypedValue::U64(4)));
+    assert_eq!(mmv.value_of("written_instances", Some("x")), Some(TypedValue::U64(6)));
+}
+
+#[test]
+fn test_name_prefix() {
+    use super::MMV1_NAME_MAX_LEN;
+    use super::mmv::{dump, TypedValue};
+    use self::metric::{Counter, Metric, Semantics, Unit};
+
+    for prefix in &["", ".myapp", "myapp.", "my app"] {
+        assert!(Client::new("name_prefix_test").unwrap().with_name_prefix(prefix).is_err());
+    }
+
+    let mut requests = Counter::new("requests", 3, "", "").unwrap();
+    let client = Client::new("name_prefix_test").unwrap()
+        .with_name_prefix("myapp.http").unwrap();
+    assert_eq!(client.name_prefix(), Some("myapp.http"));
+    client.export(&mut [&mut requests]).unwrap();
+
+    let mmv = dump(client.mmv_path()).unwrap();
+    assert_eq!(mmv.header().version(), Version::V1);
+    assert_eq!(mmv.value_of("myapp.http.requests", None), Some(TypedValue::U64(3)));
+    assert_eq!(mmv.value_of("requests", None), None);
+    assert_eq!(client.exported_metrics()[0].name(), "myapp.http.requests");
+
+    // only too long for version 1 once prefixed
+    let name = "n".repeat(MMV1_NAME_MAX_LEN as usize - 10);
+    let mut metric = Metric::new(&name, 1u32, Semantics::Discrete, Unit::new(), "", "").unwrap();
+    let client = Client::new("name_prefix_v2_test").unwrap()
+        .with_name_prefix("myapp.http").unwrap();
+    assert!(client.planned_size(&mut [&mut metric], Version::V1).is_err());
+    client.export(&mut [&mut metric]).unwrap();
+
+    let mmv = dump(client.mmv_path()).unwrap();
+    assert_eq!(mmv.header().version(), Version::V2);
+    assert_eq!(mmv.value_of(&format!("myapp.http.{}", name), None), Some(TypedValue::U32(1)));
+}
+
+#[test]
+fn test_client_name() {
+    for name in &["", ".", "..", "a/b", "a\\b", "/abs"] {
+        match Client::new(name) {
+            Err(err) => assert_eq!(err.kind(), io::ErrorKind::InvalidInput),
+            Ok(_) => panic!("accepted client name {:?}", name)
         }
+        assert!(Client::new_custom(name, MMVFlags::empty(), 0).is_err());
+        assert!(Client::new_per_process(name).is_err());
+    }
+
+    let client = Client::new("client_name").unwrap();
+    assert_eq!(client.name(), "client_name");
+    assert_eq!(client.mmv_path().file_name().unwrap(), "client_name");
+
+    let client = Client::new_per_process("client_name").unwrap().with_pid(42);
+    assert_eq!(client.name(), "client_name");
+    assert_eq!(client.mmv_path().file_name().unwrap(), "client_name-42");
+}
+
+#[cfg(windows)]
+#[test]
+fn test_long_path() {
+    use self::metric::Counter;
+
+    let mut long_dir = env::temp_dir();
+    for component in &["a", "b", "c"] {
+        long_dir.push(component.repeat(100));
     }
+    assert!(long_dir.to_str().unwrap().len() > 260);
+
+    let long_dir = to_long_path(long_dir);
+    assert!(long_dir.to_str().unwrap().starts_with(r"\\?\"));
+    assert_eq!(to_long_path(long_dir.clone()), long_dir);
+    create_mmv_dir(&long_dir).unwrap();
+
+    let mut client = Client::new("long_path_test").unwrap();
+    client.mmv_path = long_dir.join("long_path_test");
+
+    let mut counter = Counter::new("long_path_counter", 1, "", "").unwrap();
+    client.export(&mut [&mut counter]).unwrap();
+    assert!(client.mmv_path().exists());
+}
+
+#[test]
+fn test_export_verified() {
+    use self::metric::Counter;
+
+    let client = Client::new("export_verified_test").unwrap();
+    let mut counter = Counter::new("verified", 1, "", "").unwrap();
+    client.export_verified(&mut [&mut counter]).unwrap();
+
+    // corrupt the MMV's magic
+    {
+        let mut file = OpenOptions::new().write(true).open(client.mmv_path()).unwrap();
+        file.write_all(b"X").unwrap();
+    }
+    assert_eq!(client.verify().unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+    // re-export a smaller MMV behind the client's back
+    client.export_verified(&mut [&mut counter]).unwrap();
+    Client::new("export_verified_test").unwrap().export(&mut []).unwrap();
+    assert!(client.verify().is_err());
+}
+
+#[test]
+fn test_export_blocking_send() {
+    use std::thread;
+    use self::metric::{Counter, Gauge};
+
+    fn assert_send<T: Send>() {}
+    assert_send::<Client>();
+    assert_send::<Counter>();
+
+    // the blocking export can run on another thread, as it would in a
+    // runtime's blocking pool
+    let mut counter = Counter::new("blocking_counter", 1, "", "").unwrap();
+    let mut gauge = Gauge::new("blocking_gauge", 0.5, "", "").unwrap();
+    let client = Client::new("export_blocking_test").unwrap();
+    let (client, mut counter) = thread::spawn(move || {
+        client.export_blocking(&mut [&mut counter, &mut gauge]).unwrap();
+        (client, counter)
+    }).join().unwrap();
+
+    counter.up().unwrap();
+    assert_eq!(client.exported_metrics().len(), 2);
+}
+
+#[test]
+fn test_client_sync() {
+    use std::sync::Arc;
+    use std::thread;
+    use self::metric::Counter;
+
+    fn assert_sync<T: Sync>() {}
+    assert_sync::<Client>();
+
+    let client = Arc::new(Client::new("client_sync_test").unwrap());
+    let mut counter = Counter::new("sync_counter", 1, "", "").unwrap();
+    client.export(&mut [&mut counter]).unwrap();
+
+    let reader = client.clone();
+    let exported = thread::spawn(move || reader.exported_metrics()).join().unwrap();
+    assert_eq!(exported[0].name(), "sync_counter");
+
+    // holding the returned information doesn't block exporting
+    let exported = client.exported_metrics();
+    client.export(&mut [&mut counter]).unwrap();
+    assert_eq!(exported.len(), client.exported_metrics().len());
 }
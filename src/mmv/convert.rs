@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::*;
+use super::super::client::metric::{
+    Indom, InstanceMetric, Metric, MetricSet, Semantics, Unit
+};
+
+// metric block fields needed to create a metric, with the indom (if
+// any) already converted
+struct MetricDesc<'a> {
+    name: &'a str,
+    sem: Semantics,
+    unit: Unit,
+    shorthelp: &'a str,
+    longhelp: &'a str,
+    indom: Option<Arc<Indom>>,
+    // values paired with the external id of their instance
+    values: Vec<(Option<&'a str>, TypedValue)>
+}
+
+macro_rules! add_metric (
+    ($set:expr, $desc:expr, $variant:ident, $default:expr) => ({
+        let desc = $desc;
+        let mut values = Vec::with_capacity(desc.values.len());
+        for (instance, value) in desc.values {
+            match value {
+                TypedValue::$variant(value) => values.push((instance, value)),
+                _ => { return_mmvdumperror!("Value type doesn't match metric type", desc.name); }
+            }
+        }
+
+        match desc.indom {
+            Some(indom) => {
+                let mut im = InstanceMetric::new_shared(
+                    indom, desc.name, $default, desc.sem, desc.unit,
+                    desc.shorthelp, desc.longhelp
+                ).map_err(MMVDumpError::InvalidMMV)?;
+                for (instance, value) in values {
+                    let instance = match instance {
+                        Some(instance) => instance,
+                        None => { return_mmvdumperror!("Instance value without instance", desc.name); }
+                    };
+                    match im.set_val(instance, value) {
+                        Some(res) => res?,
+                        None => { return_mmvdumperror!("Value for unknown instance", instance); }
+                    }
+                }
+                $set.add(im);
+            },
+            None => {
+                let value = values.into_iter().next()
+                    .map(|(_, value)| value)
+                    .unwrap_or($default);
+                let metric = Metric::new(
+                    desc.name, value, desc.sem, desc.unit,
+                    desc.shorthelp, desc.longhelp
+                ).map_err(MMVDumpError::InvalidMMV)?;
+                $set.add(metric);
+            }
+        }
+    })
+);
+
+impl MMV {
+    /// Reconstructs the metrics stored in the MMV, with their current
+    /// values, so they can be (modified and) exported again
+    ///
+    /// Metrics with an instance domain become `InstanceMetric`s sharing
+    /// an `Indom`, and the rest `Metric`s. As with any metric created by
+    /// hornet, their items, instance domain IDs and instance IDs are
+    /// derived from their names, and so may differ from the ones in the
+    /// MMV. The result is an error if a metric can't be recreated, e.g,
+    /// if it's name isn't valid for hornet.
+    pub fn into_metrics(self) -> Result<MetricSet, MMVDumpError> {
+        let mut indoms = HashMap::new();
+        let mut set = MetricSet::new();
+
+        for (metric_offset, metric_blk) in &self.metric_blks {
+            let name = self.resolve_required(metric_blk.name(), "Metric name absent")?;
+
+            let sem = match Semantics::from_u32(metric_blk.sem) {
+                Some(sem) => sem,
+                None => { return_mmvdumperror!("Invalid semantics", metric_blk.sem); }
+            };
+
+            let indom = match metric_blk.indom {
+                Some(indom_id) => {
+                    if !indoms.contains_key(&indom_id) {
+                        let indom = self.convert_indom(indom_id)?;
+                        indoms.insert(indom_id, Arc::new(indom));
+                    }
+                    indoms.get(&indom_id).cloned()
+                },
+                None => None
+            };
+
+            let mut values = Vec::new();
+            for value_blk in self.values_for_metric(*metric_offset) {
+                let instance = match value_blk.instance_offset {
+                    Some(ref instance_offset) => match self.instance_blks.get(instance_offset) {
+                        Some(instance_blk) => Some(self.resolve_required(
+                            instance_blk.external_id(), "Instance name absent"
+                        )?),
+                        None => { return_mmvdumperror!("Instance block absent", instance_offset); }
+                    },
+                    None => None
+                };
+                match self.typed_value(metric_blk, value_blk) {
+                    Some(value) => values.push((instance, value)),
+                    None => { return_mmvdumperror!("Invalid value for metric", name); }
+                }
+            }
+
+            let desc = MetricDesc {
+                name: name,
+                sem: sem,
+                unit: Unit::from_raw(metric_blk.unit),
+                shorthelp: self.help_text(&metric_blk.short_help_offset),
+                longhelp: self.help_text(&metric_blk.long_help_offset),
+                indom: indom,
+                values: values
+            };
+
+            match MTCode::try_from(metric_blk.typ)? {
+                MTCode::I32 => add_metric!(set, desc, I32, 0),
+                MTCode::U32 => add_metric!(set, desc, U32, 0),
+                MTCode::I64 => add_metric!(set, desc, I64, 0),
+                MTCode::U64 => add_metric!(set, desc, U64, 0),
+                MTCode::F32 => add_metric!(set, desc, F32, 0.0),
+                MTCode::F64 => add_metric!(set, desc, F64, 0.0),
+                MTCode::String => add_metric!(set, desc, String, String::new())
+            }
+        }
+
+        Ok(set)
+    }
+
+    fn convert_indom(&self, indom_id: u32) -> Result<Indom, MMVDumpError> {
+        let indom_offset = self.indom_blks.iter()
+            .find(|&(_, indom_blk)| indom_blk.indom == Some(indom_id))
+            .map(|(indom_offset, _)| *indom_offset);
+        let indom_offset = match indom_offset {
+            Some(indom_offset) => indom_offset,
+            None => { return_mmvdumperror!("Indom block absent", indom_id); }
+        };
+        let indom_blk = &self.indom_blks[&indom_offset];
+
+        let mut instances = Vec::new();
+        for instance_blk in self.instances_for_indom(indom_offset) {
+            instances.push(self.resolve_required(
+                instance_blk.external_id(), "Instance name absent"
+            )?);
+        }
+
+        Indom::new(
+            &instances,
+            self.help_text(&indom_blk.short_help_offset),
+            self.help_text(&indom_blk.long_help_offset)
+        ).map_err(MMVDumpError::InvalidMMV)
+    }
+
+    fn resolve_required<'a>(&'a self, string: &'a VersionSpecificString, err: &str)
+    -> Result<&'a str, MMVDumpError> {
+        match self.resolve_string(string) {
+            Some(string) => Ok(string),
+            None => { return_mmvdumperror!(err, 0); }
+        }
+    }
+
+    fn help_text(&self, offset: &Option<u64>) -> &str {
+        offset.and_then(|offset| self.string_blks.get(&offset))
+            .map(|string_blk| string_blk.string())
+            .unwrap_or("")
+    }
+}
+
+#[test]
+fn test_into_metrics() {
+    use super::super::client::Client;
+
+    // metrics, their values and instances, and help texts, keyed by name
+    fn summary(mmv: &MMV) -> Vec<String> {
+        let mut summary: Vec<String> = mmv.metric_blks().iter().map(|(offset, metric)| {
+            let mut values: Vec<String> = mmv.values_for_metric(*offset).iter().map(|value| {
+                let instance = value.instance_offset().map(|instance_offset| {
+                    let instance = &mmv.instance_blks()[&instance_offset];
+                    mmv.resolve_string(instance.external_id()).unwrap().to_owned()
+                });
+                format!("{:?}={}", instance, mmv.typed_value(metric, value).unwrap())
+            }).collect();
+            values.sort();
+
+            format!(
+                "{} {} {} {} {:?} {:?} {:?} {:?}",
+                mmv.resolve_string(metric.name()).unwrap(),
+                metric.typ(), metric.sem(), metric.unit(),
+                metric.indom().is_some(),
+                mmv.help_text(metric.short_help_offset()),
+                mmv.help_text(metric.long_help_offset()),
+                values
+            )
+        }).collect();
+        summary.sort();
+        summary
+    }
+
+    for fixture in &["mmvdump_ip2.mmv", "mmvdump_ip5.mmv", "mmvdump_ip6.mmv"] {
+        let mmv = dump(&testdata_path(fixture)).unwrap();
+        let expected = summary(&mmv);
+        let n_indoms = mmv.indom_blks().len();
+
+        let mut metrics = mmv.into_metrics().unwrap();
+        let client = Client::new(&format!("into_metrics_{}", fixture.replace(".mmv", ""))).unwrap();
+        metrics.export_with(&client).unwrap();
+
+        let reexported = dump(client.mmv_path()).unwrap();
+        assert_eq!(summary(&reexported), expected);
+        assert_eq!(reexported.indom_blks().len(), n_indoms);
+    }
+}
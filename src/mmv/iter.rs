@@ -0,0 +1,166 @@
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use memmap::{Mmap, Protection};
+use std::io::Cursor;
+use std::path::Path;
+
+use super::*;
+use super::super::VALUE_BLOCK_LEN;
+
+/// Iterator over the values in an MMV file, created by `iter_metrics`
+///
+/// Unlike `dump`, the MMV is memory-mapped instead of read, and only
+/// the blocks needed to decode the next value are parsed.
+pub struct MetricValues {
+    mmap: Mmap,
+    big_endian: bool,
+    version: Version,
+    value_sec_off: u64,
+    n_values: u64,
+    next: u64
+}
+
+/// Returns an iterator over the values stored in the MMV file at
+/// `mmv_path`, each paired with the name of it's metric
+///
+/// Values of instance metrics are named `metric[instance]`. The result
+/// is an error if the MMV header or table of contents is invalid.
+pub fn iter_metrics(mmv_path: &Path) -> Result<MetricValues, MMVDumpError> {
+    let mmap = Mmap::open_path(mmv_path, Protection::Read)?;
+
+    // see dump_from_bytes for the byte order heuristic
+    let le_res = MetricValues::read_layout::<LittleEndian>(&mmap);
+    let (big_endian, (version, value_toc)) = match le_res {
+        Ok(layout) => (false, layout),
+        Err(le_err) => match MetricValues::read_layout::<BigEndian>(&mmap) {
+            Ok(layout) => (true, layout),
+            Err(_) => return Err(le_err)
+        }
+    };
+
+    Ok(MetricValues {
+        mmap: mmap,
+        big_endian: big_endian,
+        version: version,
+        value_sec_off: value_toc.sec_offset,
+        n_values: value_toc.entries as u64,
+        next: 0
+    })
+}
+
+impl MetricValues {
+    fn cursor(&self) -> Cursor<&[u8]> {
+        Cursor::new(unsafe { self.mmap.as_slice() })
+    }
+
+    fn read_layout<E: ByteOrder>(mmap: &Mmap) -> Result<(Version, TocBlk), MMVDumpError> {
+        let mut c = Cursor::new(unsafe { mmap.as_slice() });
+        let hdr = Header::from_reader::<E, _>(&mut c)?;
+
+        for _ in 0..hdr.toc_count {
+            let toc = TocBlk::from_reader::<E, _>(&mut c)?;
+            if toc.sec == VALUES_TOC_CODE {
+                return Ok((hdr.version, toc));
+            }
+        }
+
+        return_mmvdumperror!("Value TOC absent", 0);
+    }
+
+    fn read_string<E: ByteOrder>(&self, string: VersionSpecificString) -> Result<String, MMVDumpError> {
+        match string {
+            VersionSpecificString::String(string) => Ok(string),
+            VersionSpecificString::Offset(offset) => {
+                let mut c = self.cursor();
+                c.set_position(offset);
+                Ok(StringBlk::from_reader::<E, _>(&mut c)?.string)
+            }
+        }
+    }
+
+    fn read_value<E: ByteOrder>(&self, idx: u64) -> Result<(String, TypedValue), MMVDumpError> {
+        let mut c = self.cursor();
+
+        c.set_position(self.value_sec_off + idx*VALUE_BLOCK_LEN);
+        let value = ValueBlk::from_reader::<E, _>(&mut c)?;
+
+        let metric_offset = match value.metric_offset {
+            Some(offset) => offset,
+            None => { return_mmvdumperror!("Value without metric", idx); }
+        };
+        c.set_position(metric_offset);
+        let metric = MetricBlk::from_reader::<E, _>(&mut c, self.version, false)?;
+
+        let mut name = self.read_string::<E>(metric.name)?;
+        if let Some(instance_offset) = value.instance_offset {
+            c.set_position(instance_offset);
+            let instance = InstanceBlk::from_reader::<E, _>(&mut c, self.version, false)?;
+            let instance_name = self.read_string::<E>(instance.external_id)?;
+            name = format!("{}[{}]", name, instance_name);
+        }
+
+        let mtcode = MTCode::try_from(metric.typ)?;
+        let typed_value = match mtcode {
+            MTCode::String => {
+                let string_offset = match value.string_offset {
+                    Some(offset) => offset,
+                    None => { return_mmvdumperror!("String value without offset", idx); }
+                };
+                let string = self.read_string::<E>(VersionSpecificString::Offset(string_offset))?;
+                TypedValue::String(string)
+            },
+            _ => {
+                let mut raw = value.value;
+                // see dump_with_byte_order
+                if self.big_endian && is_32bit_type(metric.typ) {
+                    raw >>= 32;
+                }
+                TypedValue::from_numeric(mtcode, raw).unwrap()
+            }
+        };
+
+        Ok((name, typed_value))
+    }
+}
+
+impl Iterator for MetricValues {
+    type Item = Result<(String, TypedValue), MMVDumpError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.n_values {
+            return None;
+        }
+
+        let idx = self.next;
+        self.next += 1;
+        Some(if self.big_endian {
+            self.read_value::<BigEndian>(idx)
+        } else {
+            self.read_value::<LittleEndian>(idx)
+        })
+    }
+}
+
+#[test]
+fn test_iter_metrics() {
+    for fixture in &["mmvdump_ip2.mmv", "mmvdump_ip6.mmv"] {
+        let mmv_path = testdata_path(fixture);
+        let mmv = dump(&mmv_path).unwrap();
+
+        let expected: Vec<(String, TypedValue)> = mmv.value_blks().values().map(|value| {
+            let metric = mmv.metric_blks().get(&value.metric_offset().unwrap()).unwrap();
+            let mut name = mmv.resolve_string(metric.name()).unwrap().to_owned();
+            if let Some(ref instance_offset) = *value.instance_offset() {
+                let instance = mmv.instance_blks().get(instance_offset).unwrap();
+                name = format!("{}[{}]", name, mmv.resolve_string(instance.external_id()).unwrap());
+            }
+            (name, mmv.typed_value(metric, value).unwrap())
+        }).collect();
+
+        let streamed: Vec<(String, TypedValue)> = iter_metrics(&mmv_path).unwrap()
+            .map(|res| res.unwrap())
+            .collect();
+
+        assert!(!streamed.is_empty());
+        assert_eq!(streamed, expected);
+    }
+}
@@ -0,0 +1,138 @@
+use super::*;
+use super::super::client::metric::{Semantics, Unit};
+
+fn escape(string: &str) -> String {
+    let mut escaped = String::with_capacity(string.len() + 2);
+    escaped.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c)
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn opt_string(string: Option<&str>) -> String {
+    string.map_or("null".to_owned(), escape)
+}
+
+fn opt_num<T: fmt::Display>(num: Option<T>) -> String {
+    num.map_or("null".to_owned(), |num| format!("{}", num))
+}
+
+// JSON has no representation for NaN or infinities
+fn format_value(val: &TypedValue) -> String {
+    match *val {
+        TypedValue::F32(val) if !val.is_finite() => "null".to_owned(),
+        TypedValue::F64(val) if !val.is_finite() => "null".to_owned(),
+        TypedValue::String(ref val) => escape(val),
+        ref val => format!("{}", val)
+    }
+}
+
+fn help(mmv: &MMV, offset: &Option<u64>) -> String {
+    opt_string(offset.as_ref()
+        .and_then(|offset| mmv.string_blks().get(offset))
+        .map(|string| string.string()))
+}
+
+fn instance_name<'a>(mmv: &'a MMV, offset: &Option<u64>) -> Option<&'a str> {
+    offset.as_ref()
+        .and_then(|offset| mmv.instance_blks().get(offset))
+        .and_then(|instance| mmv.resolve_string(instance.external_id()))
+}
+
+/// Returns a parsed MMV serialized as a JSON object
+///
+/// The object has a `header` with the MMV's header fields, a `metrics`
+/// array and an `indoms` array. Each metric lists its `values`, each
+/// with the external name of its `instance`, or `null` for singleton
+/// metrics. Non-finite floating point values are written as `null`.
+pub fn to_json(mmv: &MMV) -> String {
+    let hdr = mmv.header();
+    let mut output = format!(
+        "{{\"header\":{{\"version\":{},\"gen1\":{},\"gen2\":{},\"toc_count\":{},\
+         \"flags\":{},\"pid\":{},\"cluster_id\":{}}},\"metrics\":[",
+        hdr.version() as u32, hdr.gen1(), hdr.gen2(), hdr.toc_count(),
+        hdr.flags(), hdr.pid(), hdr.cluster_id()
+    );
+
+    let mut first_metric = true;
+    for (offset, metric) in mmv.metric_blks() {
+        if !first_metric { output.push(','); }
+        first_metric = false;
+
+        let sem = Semantics::from_u32(metric.sem()).map(|sem| sem.as_str());
+        output.push_str(&format!(
+            "{{\"name\":{},\"item\":{},\"type\":{},\"sem\":{},\"unit\":{},\
+             \"indom\":{},\"shorthelp\":{},\"longhelp\":{},\"values\":[",
+            opt_string(mmv.resolve_string(metric.name())),
+            opt_num(*metric.item()),
+            metric.typ(),
+            opt_string(sem),
+            escape(&Unit::from_raw(metric.unit()).describe()),
+            opt_num(*metric.indom()),
+            help(mmv, metric.short_help_offset()),
+            help(mmv, metric.long_help_offset())
+        ));
+
+        let mut first_value = true;
+        for value in mmv.values_for_metric(*offset) {
+            if !first_value { output.push(','); }
+            first_value = false;
+
+            output.push_str(&format!(
+                "{{\"instance\":{},\"value\":{}}}",
+                opt_string(instance_name(mmv, value.instance_offset())),
+                mmv.typed_value(metric, value).as_ref()
+                    .map_or("null".to_owned(), format_value)
+            ));
+        }
+        output.push_str("]}");
+    }
+    output.push_str("],\"indoms\":[");
+
+    let mut first_indom = true;
+    for (offset, indom) in mmv.indom_blks() {
+        if !first_indom { output.push(','); }
+        first_indom = false;
+
+        output.push_str(&format!(
+            "{{\"indom\":{},\"shorthelp\":{},\"longhelp\":{},\"instances\":[",
+            opt_num(*indom.indom()),
+            help(mmv, indom.short_help_offset()),
+            help(mmv, indom.long_help_offset())
+        ));
+
+        let mut first_instance = true;
+        for instance in mmv.instance_blks().values() {
+            if *instance.indom_offset() != Some(*offset) {
+                continue;
+            }
+            if !first_instance { output.push(','); }
+            first_instance = false;
+
+            output.push_str(&format!(
+                "{{\"id\":{},\"name\":{}}}",
+                instance.internal_id(),
+                opt_string(mmv.resolve_string(instance.external_id()))
+            ));
+        }
+        output.push_str("]}");
+    }
+    output.push_str("]}");
+
+    output
+}
+
+#[test]
+fn test_escape() {
+    assert_eq!(escape("a\"b\\c\nd\u{1}"), "\"a\\\"b\\\\c\\nd\\u0001\"");
+}
@@ -0,0 +1,187 @@
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt};
+use memmap::{Mmap, Protection};
+use std::io;
+use std::io::Cursor;
+use std::path::Path;
+
+use super::*;
+use super::super::HDR_LEN;
+
+/// Read-only, memory-mapped view of an MMV file that may be
+/// concurrently updated by the exporting process
+///
+/// Exporters (including hornet's `Client`) lock an MMV by writing
+/// `gen1` first and `0` to `gen2`, and unlock it by writing `gen2`
+/// last, once every other block has been written. Hence, the MMV is
+/// only consistent if both generation numbers are equal.
+pub struct LiveMMV {
+    mmap: Mmap,
+    big_endian: bool
+}
+
+impl LiveMMV {
+    /// Memory-maps the MMV file stored at `mmv_path` for reading
+    pub fn open(mmv_path: &Path) -> io::Result<Self> {
+        Ok(LiveMMV::new(Mmap::open_path(mmv_path, Protection::Read)?))
+    }
+
+    /// Memory-maps the MMV file stored at `mmv_path` privately, i.e,
+    /// copy-on-write
+    ///
+    /// Pages written through a private mapping are copied, so the
+    /// exporter's file is never modified by the reader. Whether later
+    /// writes by the exporter are visible through the mapping is
+    /// unspecified, hence `read_consistent` still has to be used.
+    pub fn open_private(mmv_path: &Path) -> io::Result<Self> {
+        Ok(LiveMMV::new(Mmap::open_path(mmv_path, Protection::ReadCopy)?))
+    }
+
+    // the byte order is guessed from the header once, in the same way as
+    // `dump` does; an MMV that's still being created is assumed to be
+    // little-endian
+    fn new(mmap: Mmap) -> Self {
+        let big_endian = {
+            let bytes = unsafe { mmap.as_slice() };
+            Header::from_reader::<LittleEndian, _>(&mut Cursor::new(bytes)).is_err()
+                && Header::from_reader::<BigEndian, _>(&mut Cursor::new(bytes)).is_ok()
+        };
+        LiveMMV {
+            mmap: mmap,
+            big_endian: big_endian
+        }
+    }
+
+    fn gens(&self) -> Option<(i64, i64)> {
+        if self.big_endian {
+            self.gens_with_byte_order::<BigEndian>()
+        } else {
+            self.gens_with_byte_order::<LittleEndian>()
+        }
+    }
+
+    fn gens_with_byte_order<E: ByteOrder>(&self) -> Option<(i64, i64)> {
+        if (self.mmap.len() as u64) < HDR_LEN {
+            return None;
+        }
+
+        let mut c = Cursor::new(unsafe { self.mmap.as_slice() });
+        c.set_position(GEN1_OFFSET);
+        let gen1 = c.read_i64::<E>().ok()?;
+        c.set_position(GEN2_OFFSET);
+        let gen2 = c.read_i64::<E>().ok()?;
+        Some((gen1, gen2))
+    }
+
+    /// Checks if the MMV is currently unlocked, i.e, the generation
+    /// numbers in it's header match
+    pub fn is_consistent(&self) -> bool {
+        match self.gens() {
+            Some((gen1, gen2)) => gen1 == gen2,
+            None => false
+        }
+    }
+
+    /// Returns an `MMV` structure by parsing a snapshot of the mapped
+    /// MMV
+    ///
+    /// If the MMV is locked before or after the snapshot is taken, or
+    /// it's generation changes while taking it, the snapshot is retaken
+    /// up to `retries` times before returning an error.
+    pub fn read_consistent(&self, retries: usize) -> Result<MMV, MMVDumpError> {
+        for _ in 0..retries + 1 {
+            let before = match self.gens() {
+                Some((gen1, gen2)) if gen1 == gen2 => gen1,
+                _ => continue
+            };
+
+            let mmv_bytes = unsafe { self.mmap.as_slice() }.to_vec();
+
+            match self.gens() {
+                Some((gen1, gen2)) if gen1 == gen2 && gen1 == before => {
+                    return dump_from_bytes(mmv_bytes, false);
+                },
+                _ => continue
+            }
+        }
+
+        return_mmvdumperror!("Generation timestamps don't match", 0);
+    }
+}
+
+#[test]
+fn test_consistency() {
+    use byteorder::WriteBytesExt;
+    use std::fs::OpenOptions;
+    use std::io::{Seek, SeekFrom};
+    use super::super::Endian;
+    use super::super::client::Client;
+    use super::super::client::metric::{Metric, Semantics, Unit};
+
+    let mut metric = Metric::new(
+        "live", 1u32, Semantics::Instant, Unit::new(), "", ""
+    ).unwrap();
+
+    let client = Client::new("live_mmv_test").unwrap();
+    client.export(&mut [&mut metric]).unwrap();
+
+    let live = LiveMMV::open(client.mmv_path()).unwrap();
+    assert!(live.is_consistent());
+    assert_eq!(live.read_consistent(0).unwrap().metric_blks().len(), 1);
+
+    let mut file = OpenOptions::new().write(true)
+        .open(client.mmv_path()).unwrap();
+    file.seek(SeekFrom::Start(GEN2_OFFSET)).unwrap();
+    file.write_i64::<Endian>(0).unwrap();
+
+    assert!(!live.is_consistent());
+    assert!(live.read_consistent(3).is_err());
+}
+
+#[test]
+fn test_open_private() {
+    use byteorder::WriteBytesExt;
+    use super::super::Endian;
+    use super::super::client::Client;
+    use super::super::client::metric::{Metric, Semantics, Unit};
+
+    let mut metric = Metric::new(
+        "live_private", 1u32, Semantics::Instant, Unit::new(), "", ""
+    ).unwrap();
+
+    let client = Client::new("live_mmv_private_test").unwrap();
+    client.export(&mut [&mut metric]).unwrap();
+
+    let mut live = LiveMMV::open_private(client.mmv_path()).unwrap();
+    assert!(live.is_consistent());
+    let mmv = live.read_consistent(0).unwrap();
+    assert_eq!(mmv.value_of("live_private", None), Some(TypedValue::U32(1)));
+
+    // lock the mapped copy only
+    {
+        let mut slice = unsafe { &mut live.mmap.as_mut_slice()[GEN2_OFFSET as usize..] };
+        slice.write_i64::<Endian>(0).unwrap();
+    }
+    assert!(!live.is_consistent());
+
+    let on_disk = LiveMMV::open(client.mmv_path()).unwrap();
+    assert!(on_disk.is_consistent());
+    assert_eq!(dump(client.mmv_path()).unwrap(), mmv);
+}
+
+#[test]
+fn test_big_endian() {
+    use std::env;
+    use std::fs;
+
+    let (bytes, _, _) = big_endian_test_mmv();
+    let mmv_path = env::temp_dir().join("hornet_live_big_endian.mmv");
+    fs::write(&mmv_path, &bytes).unwrap();
+
+    let live = LiveMMV::open(&mmv_path).unwrap();
+    assert_eq!(live.gens(), Some((42, 42)));
+    assert!(live.is_consistent());
+    let mmv = live.read_consistent(0).unwrap();
+    assert_eq!(mmv.value_of("big", None), Some(TypedValue::U32(99)));
+
+    fs::remove_file(&mmv_path).unwrap();
+}
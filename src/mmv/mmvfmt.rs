@@ -1,5 +1,5 @@
 use super::*;
-use super::super::client::MMVFlags;
+use super::super::client::RawMMVFlags;
 use super::super::client::metric::{Semantics, Unit};
 use std::mem;
 
@@ -10,7 +10,7 @@ impl fmt::Display for Header {
         writeln!(f, "TOC count  = {}", self.toc_count())?;
         writeln!(f, "Cluster    = {}", self.cluster_id())?;
         writeln!(f, "Process    = {}", self.pid())?;
-        writeln!(f, "Flags      = {}", MMVFlags::from_bits_truncate(self.flags()))
+        writeln!(f, "Flags      = {}", RawMMVFlags(self.flags()))
     }
 }
 
@@ -30,8 +30,7 @@ fn write_indoms(f: &mut fmt::Formatter, indom_toc: &TocBlk, mmv: &MMV) -> fmt::R
             write!(f, "      ")?;
             match *indom.short_help_offset() {
                 Some(ref short_help_offset) => {
-                    let shortext = mmv.string_blks().get(short_help_offset).unwrap().string();
-                    writeln!(f, "shorttext={}", shortext)?;
+                    writeln!(f, "shorttext={}", string_at(mmv, short_help_offset))?;
                 }
                 None => writeln!(f, "(no shorttext)")?
             }
@@ -39,8 +38,7 @@ fn write_indoms(f: &mut fmt::Formatter, indom_toc: &TocBlk, mmv: &MMV) -> fmt::R
             write!(f, "      ")?;
             match *indom.long_help_offset() {
                 Some(ref long_help_offset) => {
-                    let longtext = mmv.string_blks().get(long_help_offset).unwrap().string();
-                    writeln!(f, "longtext={}", longtext)?
+                    writeln!(f, "longtext={}", string_at(mmv, long_help_offset))?
                 }
                 None => writeln!(f, "(no longtext)")?
             }
@@ -50,14 +48,19 @@ fn write_indoms(f: &mut fmt::Formatter, indom_toc: &TocBlk, mmv: &MMV) -> fmt::R
     Ok(())
 }
 
+// placeholder for an offset that doesn't point to a block of the
+// expected kind, which malformed MMVs can have
+const DANGLING: &str = "(dangling offset)";
+
+fn string_at<'a>(mmv: &'a MMV, offset: &u64) -> &'a str {
+    mmv.string_blks().get(offset).map_or(DANGLING, |string| string.string())
+}
+
 // note: doesn't write newline at the end
 fn write_version_specific_string(f: &mut fmt::Formatter, string: &VersionSpecificString, mmv: &MMV) -> fmt::Result {
     match string {
         &VersionSpecificString::String(ref string) => write!(f, "{}", string),
-        &VersionSpecificString::Offset(ref offset) => {
-            let string = mmv.string_blks().get(offset).unwrap().string();
-            write!(f, "{}", string)
-        }
+        &VersionSpecificString::Offset(ref offset) => write!(f, "{}", string_at(mmv, offset))
     }
 }
 
@@ -69,10 +72,10 @@ fn write_instances(f: &mut fmt::Formatter, instance_toc: &TocBlk, mmv: &MMV) ->
         write!(f, "  ")?;
         match *instance.indom_offset() {
             Some(ref indom_offset) => {
-                let indom = mmv.indom_blks().get(indom_offset).unwrap();
-                match *indom.indom() {
-                    Some(ref indom_id) => write!(f, "[{}", indom_id)?,
-                    None => write!(f, "[(no indom)")?
+                match mmv.indom_blks().get(indom_offset).map(|indom| *indom.indom()) {
+                    Some(Some(indom_id)) => write!(f, "[{}", indom_id)?,
+                    Some(None) => write!(f, "[(no indom)")?,
+                    None => write!(f, "[{}", DANGLING)?
                 }
             },
             None => write!(f, "[(no indom)")?
@@ -119,8 +122,7 @@ fn write_metrics(f: &mut fmt::Formatter, metric_toc: &TocBlk, mmv: &MMV) -> fmt:
             write!(f, "      ")?;
             match *metric.short_help_offset() {
                 Some(ref short_help_offset) => {
-                    let shortext = mmv.string_blks().get(short_help_offset).unwrap().string();
-                    writeln!(f, "shorttext={}", shortext)?;
+                    writeln!(f, "shorttext={}", string_at(mmv, short_help_offset))?;
                 }
                 None => writeln!(f, "(no shorttext)")?
             }
@@ -128,8 +130,7 @@ fn write_metrics(f: &mut fmt::Formatter, metric_toc: &TocBlk, mmv: &MMV) -> fmt:
             write!(f, "      ")?;
             match *metric.long_help_offset() {
                 Some(ref long_help_offset) => {
-                    let longtext = mmv.string_blks().get(long_help_offset).unwrap().string();
-                    writeln!(f, "longtext={}", longtext)?;
+                    writeln!(f, "longtext={}", string_at(mmv, long_help_offset))?;
                 }
                 None => writeln!(f, "(no longtext)")?
             }
@@ -145,23 +146,35 @@ fn write_values(f: &mut fmt::Formatter, value_toc: &TocBlk, mmv: &MMV) -> fmt::R
 
     for (offset, value) in mmv.value_blks() {
         if let Some(ref metric_offset) = *value.metric_offset() {
-            let metric = mmv.metric_blks().get(&metric_offset).unwrap();
+            let metric = match mmv.metric_blks().get(&metric_offset) {
+                Some(metric) => metric,
+                None => {
+                    writeln!(f, "  [{}/{}]", DANGLING, offset)?;
+                    continue;
+                }
+            };
             if let Some(item) = *metric.item() {
                 write!(f, "  [{}/{}] ", item, offset)?;
                 write_version_specific_string(f, metric.name(), mmv)?;
 
                 if let Some(ref instance_offset) = *value.instance_offset() {
-                    let instance = mmv.instance_blks().get(&instance_offset).unwrap();
-                    write!(f, "[{} or \"", instance.internal_id())?;
-                    write_version_specific_string(f, instance.external_id(), mmv)?;
-                    write!(f, "\"]")?;
+                    match mmv.instance_blks().get(&instance_offset) {
+                        Some(instance) => {
+                            write!(f, "[{} or \"", instance.internal_id())?;
+                            write_version_specific_string(f, instance.external_id(), mmv)?;
+                            write!(f, "\"]")?;
+                        },
+                        None => write!(f, "[{}]", DANGLING)?
+                    }
                 }
 
                 write!(f, " = ")?;
                 match *value.string_offset() {
                     Some(ref string_offset) => {
-                        let string = mmv.string_blks().get(string_offset).unwrap();
-                        writeln!(f, "\"{}\"", string.string())?;
+                        match mmv.string_blks().get(string_offset) {
+                            Some(string) => writeln!(f, "\"{}\"", string.string())?,
+                            None => writeln!(f, "{}", DANGLING)?
+                        }
                     }
                     None => {
                         match MTCode::from_u32(metric.typ()) {
@@ -1,23 +1,34 @@
-use byteorder::ReadBytesExt;
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt};
 use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::error;
 use std::ffi::CStr; // Used to read null-terminated strings in MMV files
 use std::fmt;
 use std::fs::File;
 use std::io;
 use std::io::Cursor;
 use std::io::prelude::*;
+use std::mem;
 use std::path::Path;
 use std::str;
 
 mod mmvfmt;
 
+mod prometheus;
+pub use self::prometheus::to_prometheus;
+mod json;
+pub use self::json::to_json;
+
 const INDOM_TOC_CODE: u32 = 1;
 const INSTANCE_TOC_CODE: u32 = 2;
 const METRIC_TOC_CODE: u32 = 3;
 const VALUES_TOC_CODE: u32 = 4;
 const STRINGS_TOC_CODE: u32 = 5;
 
-#[derive(Copy, Clone)]
+const GEN1_OFFSET: u64 = 8;
+const GEN2_OFFSET: u64 = 16;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 /// MMV code for a metric type
 ///
 /// For reference to the C API, see
@@ -54,6 +65,24 @@ impl MTCode {
     }
 }
 
+/// Error carrying an invalid MMV metric type code
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct InvalidMTCode(pub u32);
+
+impl fmt::Display for InvalidMTCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid metric type code: {}", self.0)
+    }
+}
+
+impl TryFrom<u32> for MTCode {
+    type Error = InvalidMTCode;
+
+    fn try_from(x: u32) -> Result<Self, InvalidMTCode> {
+        MTCode::from_u32(x).ok_or(InvalidMTCode(x))
+    }
+}
+
 impl fmt::Display for MTCode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -69,8 +98,8 @@ impl fmt::Display for MTCode {
     }
 }
 
+use super::client::PROCESS;
 use super::{
-    Endian,
     MMV1_NAME_MAX_LEN,
     STRING_BLOCK_LEN,
     CLUSTER_ID_BIT_LEN,
@@ -94,6 +123,13 @@ fn is_valid_blk_offset(offset: u64) -> bool {
     offset != 0
 }
 
+fn is_32bit_type(typ: u32) -> bool {
+    match MTCode::from_u32(typ) {
+        Some(MTCode::I32) | Some(MTCode::U32) | Some(MTCode::F32) => true,
+        _ => false
+    }
+}
+
 /// Error encountered while reading and parsing an MMV
 #[derive(Debug)]
 pub enum MMVDumpError {
@@ -105,6 +141,26 @@ pub enum MMVDumpError {
     Utf8(str::Utf8Error)
 }
 
+impl fmt::Display for MMVDumpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MMVDumpError::InvalidMMV(ref err) => write!(f, "Invalid MMV: {}", err),
+            MMVDumpError::Io(ref err) => write!(f, "IO error while reading MMV: {}", err),
+            MMVDumpError::Utf8(ref err) => write!(f, "Invalid UTF-8 in MMV string: {}", err)
+        }
+    }
+}
+
+impl error::Error for MMVDumpError {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match *self {
+            MMVDumpError::InvalidMMV(_) => None,
+            MMVDumpError::Io(ref err) => Some(err),
+            MMVDumpError::Utf8(ref err) => Some(err)
+        }
+    }
+}
+
 impl From<io::Error> for MMVDumpError {
     fn from(err: io::Error) -> MMVDumpError {
         MMVDumpError::Io(err)
@@ -117,6 +173,18 @@ impl From<str::Utf8Error> for MMVDumpError {
     }
 }
 
+impl From<InvalidMTCode> for MMVDumpError {
+    fn from(err: InvalidMTCode) -> MMVDumpError {
+        MMVDumpError::InvalidMMV(err.to_string())
+    }
+}
+
+impl From<InvalidVersion> for MMVDumpError {
+    fn from(err: InvalidVersion) -> MMVDumpError {
+        MMVDumpError::InvalidMMV(err.to_string())
+    }
+}
+
 macro_rules! return_mmvdumperror (
     ($err:expr, $val:expr) => (
         let mut err_str = $err.to_owned();
@@ -125,6 +193,15 @@ macro_rules! return_mmvdumperror (
     )
 );
 
+mod live;
+pub use self::live::LiveMMV;
+
+mod iter;
+pub use self::iter::{iter_metrics, MetricValues};
+
+mod convert;
+
+#[derive(Debug, PartialEq)]
 /// Top-level MMV structure
 ///
 /// The various data blocks are stored in BTreeMaps; the key for each
@@ -155,9 +232,135 @@ impl MMV {
     pub fn string_blks(&self) -> &BTreeMap<u64, StringBlk> { &self.string_blks }
     pub fn indom_blks(&self) -> &BTreeMap<u64, IndomBlk> { &self.indom_blks }
     pub fn instance_blks(&self) -> &BTreeMap<u64, InstanceBlk> { &self.instance_blks }
+
+    /// Returns the offset and number of entries of every section
+    pub fn layout(&self) -> MmvLayout {
+        MmvLayout {
+            indoms: self.indom_toc.as_ref().map(SectionLayout::from),
+            instances: self.instance_toc.as_ref().map(SectionLayout::from),
+            metrics: SectionLayout::from(&self.metric_toc),
+            values: SectionLayout::from(&self.value_toc),
+            strings: self.string_toc.as_ref().map(SectionLayout::from)
+        }
+    }
+
+    /// Returns the value blocks of the metric block at `metric_offset`
+    pub fn values_for_metric(&self, metric_offset: u64) -> Vec<&ValueBlk> {
+        self.value_blks.values()
+            .filter(|value| value.metric_offset == Some(metric_offset))
+            .collect()
+    }
+
+    /// Returns the string stored directly in, or referenced by, `string`
+    ///
+    /// The result is `None` if a referenced string block isn't present.
+    pub fn resolve_string<'a>(&'a self, string: &'a VersionSpecificString) -> Option<&'a str> {
+        match *string {
+            VersionSpecificString::String(ref string) => Some(string),
+            VersionSpecificString::Offset(ref offset) =>
+                self.string_blks.get(offset).map(|blk| blk.string())
+        }
+    }
+
+    /// Returns the value stored in `value`, decoded according to the
+    /// type of `metric`
+    ///
+    /// The result is `None` if the metric type is invalid, or if a string
+    /// value's block isn't present.
+    pub fn typed_value(&self, metric: &MetricBlk, value: &ValueBlk) -> Option<TypedValue> {
+        match MTCode::from_u32(metric.typ)? {
+            MTCode::String => {
+                let string_offset = value.string_offset?;
+                let string = self.string_blks.get(&string_offset)?.string();
+                Some(TypedValue::String(string.to_owned()))
+            },
+            mtcode => TypedValue::from_numeric(mtcode, value.value)
+        }
+    }
+
+    /// Returns the value of the metric named `metric_name`, for the
+    /// instance named `instance`, or for singleton metrics, `None`
+    ///
+    /// Names stored in string blocks (in MMV version 2) are resolved.
+    /// The result is `None` if there's no such metric or instance, or
+    /// if the value can't be decoded (see `typed_value`).
+    pub fn value_of(&self, metric_name: &str, instance: Option<&str>) -> Option<TypedValue> {
+        let (metric_offset, metric) = self.metric_blks.iter()
+            .find(|&(_, metric)| self.resolve_string(&metric.name) == Some(metric_name))?;
+
+        let value = self.values_for_metric(*metric_offset).into_iter()
+            .find(|value| {
+                let value_instance = value.instance_offset
+                    .and_then(|offset| self.instance_blks.get(&offset))
+                    .and_then(|instance| self.resolve_string(&instance.external_id));
+                value_instance == instance
+            })?;
+
+        self.typed_value(metric, value)
+    }
+
+    /// Returns the instance blocks of the indom block at `indom_offset`
+    pub fn instances_for_indom(&self, indom_offset: u64) -> Vec<&InstanceBlk> {
+        self.instance_blks.values()
+            .filter(|instance| instance.indom_offset == Some(indom_offset))
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// Value of a metric, decoded according to the metric's type
+pub enum TypedValue {
+    /// 32-bit signed integer
+    I32(i32),
+    /// 32-bit unsigned integer
+    U32(u32),
+    /// 64-bit signed integer
+    I64(i64),
+    /// 64-bit unsigned integer
+    U64(u64),
+    /// 32-bit float
+    F32(f32),
+    /// 64-bit double
+    F64(f64),
+    /// String
+    String(String)
+}
+
+impl TypedValue {
+    /// Decodes the raw value of a value block; `None` for strings,
+    /// which aren't stored in the value block
+    fn from_numeric(mtcode: MTCode, raw: u64) -> Option<Self> {
+        match mtcode {
+            MTCode::I32 => Some(TypedValue::I32(raw as i32)),
+            MTCode::U32 => Some(TypedValue::U32(raw as u32)),
+            MTCode::I64 => Some(TypedValue::I64(raw as i64)),
+            MTCode::U64 => Some(TypedValue::U64(raw)),
+            MTCode::F32 => Some(TypedValue::F32(
+                unsafe { mem::transmute::<u32, f32>(raw as u32) }
+            )),
+            MTCode::F64 => Some(TypedValue::F64(
+                unsafe { mem::transmute::<u64, f64>(raw) }
+            )),
+            MTCode::String => None
+        }
+    }
+}
+
+impl fmt::Display for TypedValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TypedValue::I32(val) => write!(f, "{}", val),
+            TypedValue::U32(val) => write!(f, "{}", val),
+            TypedValue::I64(val) => write!(f, "{}", val),
+            TypedValue::U64(val) => write!(f, "{}", val),
+            TypedValue::F32(val) => write!(f, "{}", val),
+            TypedValue::F64(val) => write!(f, "{}", val),
+            TypedValue::String(ref val) => write!(f, "{}", val)
+        }
+    }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 /// MMV version
 pub enum Version {
     /// Version 1
@@ -176,6 +379,25 @@ impl Version {
     }
 }
 
+/// Error carrying an invalid MMV version number
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct InvalidVersion(pub u32);
+
+impl fmt::Display for InvalidVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid version number: {}", self.0)
+    }
+}
+
+impl TryFrom<u32> for Version {
+    type Error = InvalidVersion;
+
+    fn try_from(x: u32) -> Result<Self, InvalidVersion> {
+        Version::from_u32(x).ok_or(InvalidVersion(x))
+    }
+}
+
+#[derive(Debug, PartialEq)]
 /// MMV header structure
 ///
 /// For reference to the C API, see
@@ -200,10 +422,55 @@ impl Header {
     pub fn flags(&self) -> u32 { self.flags }
     pub fn pid(&self) -> i32 { self.pid }
     pub fn cluster_id(&self) -> u32 { self.cluster_id }
+
+    /// Returns whether the process that exported the MMV is still running
+    ///
+    /// MMVs without the `PROCESS` flag aren't tied to the lifetime of a
+    /// process, and are always reported as alive.
+    pub fn process_alive(&self) -> bool {
+        if self.flags & PROCESS.bits() == 0 {
+            return true;
+        }
+        self.pid > 0 && is_process_running(self.pid)
+    }
+}
+
+#[cfg(unix)]
+fn is_process_running(pid: i32) -> bool {
+    use nix::Error;
+    use nix::errno::Errno;
+    use nix::sys::signal::kill;
+
+    // signal 0 only checks whether the pid can be signalled; EPERM means
+    // the process exists but belongs to another user
+    match kill(pid, None) {
+        Ok(()) => true,
+        Err(Error::Sys(Errno::EPERM)) => true,
+        Err(_) => false
+    }
+}
+
+#[cfg(windows)]
+fn is_process_running(pid: i32) -> bool {
+    use kernel32;
+
+    const PROCESS_QUERY_LIMITED_INFORMATION: u32 = 0x1000;
+    const STILL_ACTIVE: u32 = 259;
+
+    unsafe {
+        let handle = kernel32::OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid as u32);
+        if handle.is_null() {
+            return false;
+        }
+        let mut exit_code = 0;
+        let ok = kernel32::GetExitCodeProcess(handle, &mut exit_code);
+        kernel32::CloseHandle(handle);
+        ok != 0 && exit_code == STILL_ACTIVE
+    }
 }
 
 impl Header {
-    fn from_reader<R: ReadBytesExt>(r: &mut R) -> Result<Self, MMVDumpError> {
+    fn from_reader<E: ByteOrder, R: ReadBytesExt>(r: &mut R) -> Result<Self, MMVDumpError> {
         let mut magic = [0; 4];
         magic[0] = r.read_u8()?;
         magic[1] = r.read_u8()?;
@@ -213,29 +480,23 @@ impl Header {
             return_mmvdumperror!("Invalid MMV", 0);
         }
 
-        let version = r.read_u32::<Endian>()?;
-        let mmv_ver = match Version::from_u32(version) {
-            Some(ver) => ver,
-            None => {
-                return_mmvdumperror!("Invalid version number", version);
-            }
-        };
+        let mmv_ver = Version::try_from(r.read_u32::<E>()?)?;
 
-        let gen1 = r.read_i64::<Endian>()?;
-        let gen2 = r.read_i64::<Endian>()?;
+        let gen1 = r.read_i64::<E>()?;
+        let gen2 = r.read_i64::<E>()?;
         if gen1 != gen2 {
             return_mmvdumperror!("Generation timestamps don't match", 0);
         } 
 
-        let toc_count = r.read_u32::<Endian>()?;
+        let toc_count = r.read_u32::<E>()?;
         if toc_count > 5 || toc_count < 2 {
             return_mmvdumperror!("Invalid TOC count", toc_count);
         }
 
-        let flags = r.read_u32::<Endian>()?;
-        let pid = r.read_i32::<Endian>()?;
+        let flags = r.read_u32::<E>()?;
+        let pid = r.read_i32::<E>()?;
 
-        let cluster_id = r.read_u32::<Endian>()?;
+        let cluster_id = r.read_u32::<E>()?;
         if !is_valid_cluster_id(cluster_id) {
             return_mmvdumperror!("Invalid cluster ID", cluster_id);
         }
@@ -253,6 +514,7 @@ impl Header {
     }
 }
 
+#[derive(Debug, PartialEq)]
 /// MMV Table-of-Contents structure
 ///
 /// For reference to the C API, see
@@ -274,15 +536,15 @@ impl TocBlk {
 }
 
 impl TocBlk {
-    fn from_reader<R: ReadBytesExt>(r: &mut R) -> Result<Self, MMVDumpError> {
-        let sec = r.read_u32::<Endian>()?;
+    fn from_reader<E: ByteOrder, R: ReadBytesExt>(r: &mut R) -> Result<Self, MMVDumpError> {
+        let sec = r.read_u32::<E>()?;
         if sec > 5 {
             return_mmvdumperror!("Invalid TOC type", sec);
         }
 
-        let entries = r.read_u32::<Endian>()?;
+        let entries = r.read_u32::<E>()?;
 
-        let sec_offset = r.read_u64::<Endian>()?;
+        let sec_offset = r.read_u64::<E>()?;
         if !is_valid_blk_offset(sec_offset) {
             return_mmvdumperror!("Invalid section offset", sec_offset);
         }
@@ -297,6 +559,48 @@ impl TocBlk {
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+/// Offset and number of entries of an MMV section
+pub struct SectionLayout {
+    offset: u64,
+    entries: u32
+}
+
+impl SectionLayout {
+    pub fn offset(&self) -> u64 { self.offset }
+    pub fn entries(&self) -> u32 { self.entries }
+}
+
+impl<'a> From<&'a TocBlk> for SectionLayout {
+    fn from(toc: &'a TocBlk) -> Self {
+        SectionLayout {
+            offset: toc.sec_offset,
+            entries: toc.entries
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+/// Structural overview of an MMV, derived from it's TOC blocks
+///
+/// Sections without a TOC block in the MMV are `None`.
+pub struct MmvLayout {
+    indoms: Option<SectionLayout>,
+    instances: Option<SectionLayout>,
+    metrics: SectionLayout,
+    values: SectionLayout,
+    strings: Option<SectionLayout>
+}
+
+impl MmvLayout {
+    pub fn indoms(&self) -> Option<SectionLayout> { self.indoms }
+    pub fn instances(&self) -> Option<SectionLayout> { self.instances }
+    pub fn metrics(&self) -> SectionLayout { self.metrics }
+    pub fn values(&self) -> SectionLayout { self.values }
+    pub fn strings(&self) -> Option<SectionLayout> { self.strings }
+}
+
+#[derive(Debug, PartialEq)]
 /// String whose MMV representation depends on the MMV version
 pub enum VersionSpecificString {
     /// MMV version 1 direct string
@@ -305,6 +609,7 @@ pub enum VersionSpecificString {
     Offset(u64)
 }
 
+#[derive(Debug, PartialEq)]
 /// Metric block structure
 ///
 /// For reference to the C API, see
@@ -334,7 +639,7 @@ impl MetricBlk {
 }
 
 impl MetricBlk {
-    fn from_reader<R: ReadBytesExt>(r: &mut R, ver: Version) -> Result<Self, MMVDumpError> {
+    fn from_reader<E: ByteOrder, R: ReadBytesExt>(r: &mut R, ver: Version, lenient: bool) -> Result<Self, MMVDumpError> {
         let name = match ver {
             Version::V1 => {
                 let mut name_bytes = [0; MMV1_NAME_MAX_LEN as usize];
@@ -345,23 +650,23 @@ impl MetricBlk {
                 VersionSpecificString::String(cstr.to_str()?.to_owned())
             },
             Version::V2 => {
-                VersionSpecificString::Offset(r.read_u64::<Endian>()?)
+                VersionSpecificString::Offset(r.read_u64::<E>()?)
             }
         };
 
-        let item = r.read_u32::<Endian>()?;
-        let typ = r.read_u32::<Endian>()?;
-        let sem = r.read_u32::<Endian>()?;
-        let unit = r.read_u32::<Endian>()?;
-        let indom = r.read_u32::<Endian>()?;
+        let item = r.read_u32::<E>()?;
+        let typ = r.read_u32::<E>()?;
+        let sem = r.read_u32::<E>()?;
+        let unit = r.read_u32::<E>()?;
+        let indom = r.read_u32::<E>()?;
 
-        let pad = r.read_u32::<Endian>()?;
-        if pad != 0 {
+        let pad = r.read_u32::<E>()?;
+        if pad != 0 && !lenient {
             return_mmvdumperror!("Invalid pad bytes", pad);
         }
 
-        let short_help_offset = r.read_u64::<Endian>()?;
-        let long_help_offset = r.read_u64::<Endian>()?;
+        let short_help_offset = r.read_u64::<E>()?;
+        let long_help_offset = r.read_u64::<E>()?;
         
         Ok(MetricBlk {
             name: name,
@@ -389,6 +694,7 @@ impl MetricBlk {
     }
 }
 
+#[derive(Debug, PartialEq)]
 /// Value block structure
 ///
 /// For reference to the C API, see
@@ -408,11 +714,11 @@ impl ValueBlk {
 }
 
 impl ValueBlk {
-    fn from_reader<R: ReadBytesExt>(r: &mut R) -> Result<Self, MMVDumpError> {
-        let value = r.read_u64::<Endian>()?;
-        let string_offset = r.read_u64::<Endian>()?;
-        let metric_offset = r.read_u64::<Endian>()?;
-        let instance_offset = r.read_u64::<Endian>()?;
+    fn from_reader<E: ByteOrder, R: ReadBytesExt>(r: &mut R) -> Result<Self, MMVDumpError> {
+        let value = r.read_u64::<E>()?;
+        let string_offset = r.read_u64::<E>()?;
+        let metric_offset = r.read_u64::<E>()?;
+        let instance_offset = r.read_u64::<E>()?;
 
         Ok(ValueBlk {
             value: value,
@@ -432,6 +738,7 @@ impl ValueBlk {
     }
 }
 
+#[derive(Debug, PartialEq)]
 /// Indom block structure
 ///
 /// For reference to the C API, see
@@ -453,12 +760,12 @@ impl IndomBlk {
 }
 
 impl IndomBlk {
-    fn from_reader<R: ReadBytesExt>(r: &mut R) -> Result<Self, MMVDumpError> {
-        let indom = r.read_u32::<Endian>()?;
-        let instances = r.read_u32::<Endian>()?;
-        let instances_offset = r.read_u64::<Endian>()?;
-        let short_help_offset = r.read_u64::<Endian>()?;
-        let long_help_offset = r.read_u64::<Endian>()?;
+    fn from_reader<E: ByteOrder, R: ReadBytesExt>(r: &mut R) -> Result<Self, MMVDumpError> {
+        let indom = r.read_u32::<E>()?;
+        let instances = r.read_u32::<E>()?;
+        let instances_offset = r.read_u64::<E>()?;
+        let short_help_offset = r.read_u64::<E>()?;
+        let long_help_offset = r.read_u64::<E>()?;
 
         Ok(IndomBlk {
             indom: {
@@ -482,6 +789,7 @@ impl IndomBlk {
     }
 }
 
+#[derive(Debug, PartialEq)]
 /// Instance block structure
 ///
 /// For reference to the C API, see
@@ -501,15 +809,15 @@ impl InstanceBlk {
 }
 
 impl InstanceBlk {
-    fn from_reader<R: ReadBytesExt>(r: &mut R, ver: Version) -> Result<Self, MMVDumpError> {
-        let indom_offset = r.read_u64::<Endian>()?;
+    fn from_reader<E: ByteOrder, R: ReadBytesExt>(r: &mut R, ver: Version, lenient: bool) -> Result<Self, MMVDumpError> {
+        let indom_offset = r.read_u64::<E>()?;
 
-        let pad = r.read_u32::<Endian>()?;
-        if pad != 0 {
+        let pad = r.read_u32::<E>()?;
+        if pad != 0 && !lenient {
             return_mmvdumperror!("Invalid pad bytes", pad);
         }
 
-        let internal_id = r.read_i32::<Endian>()?;
+        let internal_id = r.read_i32::<E>()?;
 
         let external_id = match ver {
             Version::V1 => {
@@ -521,7 +829,7 @@ impl InstanceBlk {
                 VersionSpecificString::String(cstr.to_str()?.to_owned())
             },
             Version::V2 => {
-                VersionSpecificString::Offset(r.read_u64::<Endian>()?)
+                VersionSpecificString::Offset(r.read_u64::<E>()?)
             }
         };
         
@@ -538,6 +846,7 @@ impl InstanceBlk {
     }
 }
 
+#[derive(Debug, PartialEq)]
 /// String block structure
 ///
 /// For reference to the C API, see
@@ -551,7 +860,7 @@ impl StringBlk {
 }
 
 impl StringBlk {
-    fn from_reader<R: ReadBytesExt>(r: &mut R) -> Result<Self, MMVDumpError> {
+    fn from_reader<E: ByteOrder, R: ReadBytesExt>(r: &mut R) -> Result<Self, MMVDumpError> {
         let mut bytes = [0; STRING_BLOCK_LEN as usize];
         r.read_exact(&mut bytes)?;
         let cstr = unsafe {
@@ -566,14 +875,14 @@ impl StringBlk {
 }
 
 macro_rules! blks_from_toc (
-    ($toc:expr, $blk_typ:tt, $cursor:expr) => {
+    ($toc:expr, $blk_typ:tt, $endian:ty, $cursor:expr) => {
         if let Some(ref toc) = $toc {
             let mut blks = BTreeMap::new();
 
             $cursor.set_position(toc.sec_offset);
             for _ in 0..toc.entries as usize {
                 let blk_offset = $cursor.position();
-                let blk = $blk_typ::from_reader(&mut $cursor)?;
+                let blk = $blk_typ::from_reader::<$endian, _>(&mut $cursor)?;
                 blks.insert(blk_offset, blk);
             }
 
@@ -582,14 +891,14 @@ macro_rules! blks_from_toc (
             BTreeMap::new()
         }
     };
-    ($toc:expr, $blk_typ:tt, $mmv_ver:expr, $cursor:expr) => {
+    ($toc:expr, $blk_typ:tt, $endian:ty, $mmv_ver:expr, $lenient:expr, $cursor:expr) => {
         if let Some(ref toc) = $toc {
             let mut blks = BTreeMap::new();
 
             $cursor.set_position(toc.sec_offset);
             for _ in 0..toc.entries as usize {
                 let blk_offset = $cursor.position();
-                let blk = $blk_typ::from_reader(&mut $cursor, $mmv_ver)?;
+                let blk = $blk_typ::from_reader::<$endian, _>(&mut $cursor, $mmv_ver, $lenient)?;
                 blks.insert(blk_offset, blk);
             }
 
@@ -607,9 +916,69 @@ pub fn dump(mmv_path: &Path) -> Result<MMV, MMVDumpError> {
     let mut file = File::open(mmv_path)?;
     file.read_to_end(&mut mmv_bytes)?;
 
+    dump_from_bytes(mmv_bytes, false)
+}
+
+/// Same as `dump`, except that non-zero pad fields in metric and
+/// instance blocks are recorded in the blocks instead of being
+/// rejected
+///
+/// Meant for inspecting MMVs written by other tools, which may put
+/// data in the pad fields.
+pub fn dump_lenient(mmv_path: &Path) -> Result<MMV, MMVDumpError> {
+    let mut mmv_bytes = Vec::new();
+    let mut file = File::open(mmv_path)?;
+    file.read_to_end(&mut mmv_bytes)?;
+
+    dump_from_bytes(mmv_bytes, true)
+}
+
+/// Parses only the header of the MMV file stored at `mmv_path`
+///
+/// Much cheaper than `dump` when only the header's metadata (e.g, the
+/// cluster ID, PID or generation) is of interest, as only the first
+/// `40` bytes of the file are read.
+pub fn read_header(mmv_path: &Path) -> Result<Header, MMVDumpError> {
+    let mut hdr_bytes = Vec::with_capacity(super::HDR_LEN as usize);
+    File::open(mmv_path)?.take(super::HDR_LEN).read_to_end(&mut hdr_bytes)?;
+
+    Header::from_reader::<LittleEndian, _>(&mut Cursor::new(&hdr_bytes))
+        .or_else(|le_err|
+            Header::from_reader::<BigEndian, _>(&mut Cursor::new(&hdr_bytes))
+                .map_err(|_| le_err)
+        )
+}
+
+/// Returns the text representation of the MMV file stored at
+/// `mmv_path`, as printed by the `mmvdump` binary
+pub fn dump_to_string(mmv_path: &Path) -> Result<String, MMVDumpError> {
+    Ok(format!("{}", dump(mmv_path)?))
+}
+
+/*
+   The header magic reads the same in either byte order, so the byte order
+   of an MMV is guessed by parsing the header as little-endian (the common
+   case) and, if that doesn't yield a valid header, as big-endian. A header
+   is valid only if it's version, TOC count and cluster ID are in range,
+   which a header of the other byte order almost never satisfies.
+*/
+pub(crate) fn dump_from_bytes(mmv_bytes: Vec<u8>, lenient: bool) -> Result<MMV, MMVDumpError> {
+    let le_err = match Header::from_reader::<LittleEndian, _>(&mut Cursor::new(&mmv_bytes)) {
+        Ok(_) => return dump_with_byte_order::<LittleEndian>(mmv_bytes, lenient),
+        Err(err) => err
+    };
+
+    if Header::from_reader::<BigEndian, _>(&mut Cursor::new(&mmv_bytes)).is_ok() {
+        dump_with_byte_order::<BigEndian>(mmv_bytes, lenient)
+    } else {
+        Err(le_err)
+    }
+}
+
+fn dump_with_byte_order<E: ByteOrder>(mmv_bytes: Vec<u8>, lenient: bool) -> Result<MMV, MMVDumpError> {
     let mut cursor = Cursor::new(mmv_bytes);
     
-    let hdr = Header::from_reader(&mut cursor)?;
+    let hdr = Header::from_reader::<E, _>(&mut cursor)?;
 
     let mut indom_toc = None;
     let mut instance_toc = None;
@@ -619,7 +988,7 @@ pub fn dump(mmv_path: &Path) -> Result<MMV, MMVDumpError> {
 
     for i in 0..hdr.toc_count {
         let toc_position = cursor.position();
-        let mut toc = TocBlk::from_reader(&mut cursor)?;
+        let mut toc = TocBlk::from_reader::<E, _>(&mut cursor)?;
         toc._toc_index = i;
         toc._mmv_offset = toc_position;
 
@@ -634,14 +1003,36 @@ pub fn dump(mmv_path: &Path) -> Result<MMV, MMVDumpError> {
         return_mmvdumperror!("Metric TOC absent", 0);
     }
     if value_toc.is_none() {
-        return_mmvdumperror!("String TOC absent", 0);
+        return_mmvdumperror!("Value TOC absent", 0);
+    }
+
+    let n_tocs_found = [&indom_toc, &instance_toc, &metric_toc, &value_toc, &string_toc]
+        .iter().filter(|toc| toc.is_some()).count() as u32;
+    if n_tocs_found != hdr.toc_count {
+        return_mmvdumperror!("TOC blocks found don't match TOC count", n_tocs_found);
     }
 
-    let indom_blks = blks_from_toc!(indom_toc, IndomBlk, cursor);
-    let instance_blks = blks_from_toc!(instance_toc, InstanceBlk, hdr.version, cursor);
-    let metric_blks = blks_from_toc!(metric_toc, MetricBlk, hdr.version, cursor);
-    let value_blks = blks_from_toc!(value_toc, ValueBlk, cursor);
-    let string_blks = blks_from_toc!(string_toc, StringBlk, cursor);
+    let indom_blks = blks_from_toc!(indom_toc, IndomBlk, E, cursor);
+    let instance_blks = blks_from_toc!(instance_toc, InstanceBlk, E, hdr.version, lenient, cursor);
+    let metric_blks = blks_from_toc!(metric_toc, MetricBlk, E, hdr.version, lenient, cursor);
+    let mut value_blks = blks_from_toc!(value_toc, ValueBlk, E, cursor);
+    let string_blks = blks_from_toc!(string_toc, StringBlk, E, cursor);
+
+    /* 32-bit values are stored in the first 4 bytes of the 8-byte value
+       field, which are the most significant ones when read as a big-endian
+       u64. shift them down so values read the same in both byte orders */
+    let is_big_endian = E::read_u16(&[0, 1]) == 1;
+    if is_big_endian {
+        for value in value_blks.values_mut() {
+            let is_32bit = value.metric_offset
+                .and_then(|offset| metric_blks.get(&offset))
+                .map(|metric: &MetricBlk| is_32bit_type(metric.typ))
+                .unwrap_or(false);
+            if is_32bit {
+                value.value >>= 32;
+            }
+        }
+    }
 
     Ok(
         MMV {
@@ -659,3 +1050,296 @@ pub fn dump(mmv_path: &Path) -> Result<MMV, MMVDumpError> {
         }
     )
 }
+
+#[cfg(test)]
+fn testdata_path(file: &str) -> ::std::path::PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data").join(file)
+}
+
+#[test]
+fn test_blk_relationships() {
+    let mmv = dump(&testdata_path("mmvdump_ip2.mmv")).unwrap();
+
+    let (&metric_offset, _) = mmv.metric_blks().iter().next().unwrap();
+    let values = mmv.values_for_metric(metric_offset);
+    assert_eq!(values.len(), 3);
+    assert!(values.iter().all(|v| *v.metric_offset() == Some(metric_offset)));
+    assert!(mmv.values_for_metric(0).is_empty());
+
+    let (&indom_offset, _) = mmv.indom_blks().iter().next().unwrap();
+    assert_eq!(mmv.instances_for_indom(indom_offset).len(), 3);
+    assert!(mmv.instances_for_indom(0).is_empty());
+}
+
+#[test]
+fn test_read_header() {
+    for fixture in &["mmvdump_ip2.mmv", "mmvdump_ip6.mmv"] {
+        let mmv_path = testdata_path(fixture);
+        assert_eq!(&read_header(&mmv_path).unwrap(), dump(&mmv_path).unwrap().header());
+    }
+
+    let mmv_path = testdata_path("mmvdump_ip2.mmv");
+    let mut bytes = Vec::new();
+    File::open(&mmv_path).unwrap().read_to_end(&mut bytes).unwrap();
+    bytes[0] = b'X';
+
+    let corrupt_path = ::std::env::temp_dir().join("read_header_corrupt.mmv");
+    File::create(&corrupt_path).unwrap().write_all(&bytes).unwrap();
+    assert!(read_header(&corrupt_path).is_err());
+}
+
+#[test]
+fn test_dump_lenient() {
+    use byteorder::{LittleEndian, WriteBytesExt};
+    use super::client::Client;
+    use super::client::metric::{Metric, Semantics, Unit};
+    use super::MMV1_NAME_MAX_LEN;
+
+    let mut metric = Metric::new(
+        "padded", 1u32, Semantics::Instant, Unit::new(), "", ""
+    ).unwrap();
+    let client = Client::new("dump_lenient_test").unwrap();
+    client.export(&mut [&mut metric]).unwrap();
+
+    let mut mmv_bytes = Vec::new();
+    File::open(client.mmv_path()).unwrap().read_to_end(&mut mmv_bytes).unwrap();
+    let metric_sec_off = dump(client.mmv_path()).unwrap().metric_toc().sec_offset();
+
+    // pad follows the name and the item, type, sem, unit and indom fields
+    let pad_off = (metric_sec_off + MMV1_NAME_MAX_LEN + 5*4) as usize;
+    (&mut mmv_bytes[pad_off..pad_off + 4]).write_u32::<LittleEndian>(0xbeef).unwrap();
+
+    let padded_path = ::std::env::temp_dir().join("dump_lenient_padded.mmv");
+    File::create(&padded_path).unwrap().write_all(&mmv_bytes).unwrap();
+
+    assert!(dump(&padded_path).is_err());
+    let mmv = dump_lenient(&padded_path).unwrap();
+    let (_, metric_blk) = mmv.metric_blks().iter().next().unwrap();
+    assert_eq!(metric_blk.pad(), 0xbeef);
+}
+
+#[test]
+fn test_layout() {
+    let mmv = dump(&testdata_path("mmvdump_ip6.mmv")).unwrap();
+    let layout = mmv.layout();
+
+    assert_eq!(layout.metrics().offset(), mmv.metric_toc().sec_offset());
+    assert_eq!(layout.metrics().entries() as usize, mmv.metric_blks().len());
+    assert_eq!(layout.values().offset(), mmv.value_toc().sec_offset());
+    assert_eq!(layout.values().entries() as usize, mmv.value_blks().len());
+    assert_eq!(layout.indoms().is_some(), mmv.indom_toc().is_some());
+    assert_eq!(layout.instances().is_some(), mmv.instance_toc().is_some());
+    assert_eq!(
+        layout.strings().map(|s| s.entries() as usize),
+        mmv.string_toc().as_ref().map(|_| mmv.string_blks().len())
+    );
+}
+
+#[test]
+fn test_try_from_u32() {
+    use std::convert::TryInto;
+
+    let code: Result<MTCode, _> = 6u32.try_into();
+    assert_eq!(code, Ok(MTCode::String));
+    let code: Result<MTCode, _> = 7u32.try_into();
+    assert_eq!(code.err(), Some(InvalidMTCode(7)));
+
+    let ver: Result<Version, _> = 2u32.try_into();
+    assert_eq!(ver, Ok(Version::V2));
+    let ver: Result<Version, _> = 3u32.try_into();
+    assert_eq!(ver.err(), Some(InvalidVersion(3)));
+}
+
+// returns a big-endian MMV with a `U32` metric named `big` of value `99`,
+// and the offsets of it's metric and value blocks
+#[cfg(test)]
+fn big_endian_test_mmv() -> (Vec<u8>, u64, u64) {
+    use byteorder::WriteBytesExt;
+    use super::METRIC_BLOCK_LEN_MMV1;
+
+    let toc_off = 40;
+    let metric_off = toc_off + 2*16;
+    let value_off = metric_off + METRIC_BLOCK_LEN_MMV1;
+
+    let mut bytes = Vec::new();
+    // header
+    bytes.extend_from_slice(b"MMV\0");
+    bytes.write_u32::<BigEndian>(1).unwrap();
+    bytes.write_i64::<BigEndian>(42).unwrap();
+    bytes.write_i64::<BigEndian>(42).unwrap();
+    bytes.write_u32::<BigEndian>(2).unwrap();
+    bytes.write_u32::<BigEndian>(0).unwrap();
+    bytes.write_i32::<BigEndian>(1234).unwrap();
+    bytes.write_u32::<BigEndian>(7).unwrap();
+    // metric and value TOCs
+    bytes.write_u32::<BigEndian>(METRIC_TOC_CODE).unwrap();
+    bytes.write_u32::<BigEndian>(1).unwrap();
+    bytes.write_u64::<BigEndian>(metric_off).unwrap();
+    bytes.write_u32::<BigEndian>(VALUES_TOC_CODE).unwrap();
+    bytes.write_u32::<BigEndian>(1).unwrap();
+    bytes.write_u64::<BigEndian>(value_off).unwrap();
+    // u32 metric
+    let mut name = [0u8; MMV1_NAME_MAX_LEN as usize];
+    name[..3].copy_from_slice(b"big");
+    bytes.extend_from_slice(&name);
+    bytes.write_u32::<BigEndian>(1).unwrap();
+    bytes.write_u32::<BigEndian>(MTCode::U32 as u32).unwrap();
+    bytes.write_u32::<BigEndian>(3).unwrap();
+    bytes.write_u32::<BigEndian>(0).unwrap();
+    bytes.write_u32::<BigEndian>(0).unwrap();
+    bytes.write_u32::<BigEndian>(0).unwrap();
+    bytes.write_u64::<BigEndian>(0).unwrap();
+    bytes.write_u64::<BigEndian>(0).unwrap();
+    // value, stored in the first 4 bytes of the value field
+    bytes.write_u32::<BigEndian>(99).unwrap();
+    bytes.write_u32::<BigEndian>(0).unwrap();
+    bytes.write_u64::<BigEndian>(0).unwrap();
+    bytes.write_u64::<BigEndian>(metric_off).unwrap();
+    bytes.write_u64::<BigEndian>(0).unwrap();
+
+    (bytes, metric_off, value_off)
+}
+
+#[test]
+fn test_big_endian() {
+    let (bytes, metric_off, value_off) = big_endian_test_mmv();
+
+    let mmv = dump_from_bytes(bytes, false).unwrap();
+    assert_eq!(mmv.header().gen1(), 42);
+    assert_eq!(mmv.header().toc_count(), 2);
+    assert_eq!(mmv.header().pid(), 1234);
+    assert_eq!(mmv.header().cluster_id(), 7);
+
+    let metric = mmv.metric_blks().get(&metric_off).unwrap();
+    assert_eq!(*metric.item(), Some(1));
+    let value = mmv.value_blks().get(&value_off).unwrap();
+    assert_eq!(mmv.typed_value(metric, value), Some(TypedValue::U32(99)));
+}
+
+#[test]
+fn test_mmv_eq() {
+    let mmv_path = testdata_path("mmvdump_ip6.mmv");
+    assert_eq!(dump(&mmv_path).unwrap(), dump(&mmv_path).unwrap());
+    assert!(dump(&mmv_path).unwrap() != dump(&testdata_path("mmvdump_ip2.mmv")).unwrap());
+}
+
+#[test]
+fn test_toc_validation() {
+    use byteorder::{LittleEndian, WriteBytesExt};
+    use super::client::Client;
+    use super::client::metric::{Metric, Semantics, Unit};
+    use super::{HDR_LEN, TOC_BLOCK_LEN};
+
+    let mut metric = Metric::new(
+        "toc_validation", 1u32, Semantics::Instant, Unit::new(), "short help", ""
+    ).unwrap();
+    let client = Client::new("toc_validation_test").unwrap();
+    client.export(&mut [&mut metric]).unwrap();
+
+    let mut mmv_bytes = Vec::new();
+    File::open(client.mmv_path()).unwrap().read_to_end(&mut mmv_bytes).unwrap();
+    assert!(dump_from_bytes(mmv_bytes.clone(), false).is_ok());
+
+    // overwrites the section code of the TOC block for `sec` with 0
+    let without_toc = |sec: u32| {
+        let mut bytes = mmv_bytes.clone();
+        let toc_off = (0..3)
+            .map(|i| (HDR_LEN + i*TOC_BLOCK_LEN) as usize)
+            .find(|&off| Cursor::new(&bytes[off..]).read_u32::<LittleEndian>().unwrap() == sec)
+            .unwrap();
+        (&mut bytes[toc_off..]).write_u32::<LittleEndian>(0).unwrap();
+        bytes
+    };
+
+    match dump_from_bytes(without_toc(VALUES_TOC_CODE), false) {
+        Err(MMVDumpError::InvalidMMV(err)) => assert!(err.starts_with("Value TOC absent")),
+        _ => panic!("expected an invalid MMV")
+    }
+    match dump_from_bytes(without_toc(STRINGS_TOC_CODE), false) {
+        Err(MMVDumpError::InvalidMMV(err)) =>
+            assert!(err.starts_with("TOC blocks found don't match TOC count")),
+        _ => panic!("expected an invalid MMV")
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_process_alive() {
+    use super::client::Client;
+    use super::client::metric::{Metric, Semantics, Unit};
+    use std::i32;
+
+    let mut metric = Metric::new("alive", 1u32, Semantics::Discrete, Unit::new(), "", "").unwrap();
+    let client = Client::new("process_alive_test").unwrap();
+    client.export(&mut [&mut metric]).unwrap();
+
+    let mut header = read_header(client.mmv_path()).unwrap();
+    assert!(header.process_alive());
+
+    // beyond the maximum pid on any unix
+    header.pid = i32::MAX;
+    assert!(!header.process_alive());
+
+    header.flags = 0;
+    assert!(header.process_alive());
+}
+
+#[test]
+fn test_unknown_flags_display() {
+    use super::client::Client;
+    use super::client::metric::{Metric, Semantics, Unit};
+
+    let mut metric = Metric::new("flagged", 1u32, Semantics::Discrete, Unit::new(), "", "").unwrap();
+    let client = Client::new("unknown_flags_test").unwrap();
+    client.export(&mut [&mut metric]).unwrap();
+
+    let mut header = read_header(client.mmv_path()).unwrap();
+    assert!(format!("{}", header).ends_with("Flags      = process (0x2)\n"));
+
+    header.flags = PROCESS.bits() | 0x100;
+    assert!(format!("{}", header).ends_with("Flags      = process,unknown(0x100) (0x102)\n"));
+
+    header.flags = 0x100;
+    assert!(format!("{}", header).ends_with("Flags      = unknown(0x100) (0x100)\n"));
+}
+
+#[test]
+fn test_dump_error_display() {
+    use std::error::Error;
+
+    let err = MMVDumpError::InvalidMMV("Value TOC absent: 0".to_owned());
+    assert_eq!(format!("{}", err), "Invalid MMV: Value TOC absent: 0");
+    assert!(err.source().is_none());
+
+    let err = dump(Path::new("/nonexistent/hornet.mmv")).unwrap_err();
+    let msg = format!("{}", err);
+    assert!(msg.starts_with("IO error while reading MMV: "));
+    assert!(err.source().is_some());
+
+    let invalid_utf8 = vec![0xff, 0xfe];
+    let err = MMVDumpError::from(str::from_utf8(&invalid_utf8).unwrap_err());
+    assert!(format!("{}", err).starts_with("Invalid UTF-8 in MMV string: "));
+    assert!(err.source().is_some());
+
+    // usable with `?` into a boxed error
+    fn boxed() -> Result<(), Box<Error>> {
+        dump(Path::new("/nonexistent/hornet.mmv"))?;
+        Ok(())
+    }
+    assert!(boxed().is_err());
+}
+
+#[test]
+fn test_value_of() {
+    let mmv = dump(&testdata_path("mmvdump_ip2.mmv")).unwrap();
+    assert_eq!(mmv.value_of("language.users", Some("php")), Some(TypedValue::U64(33)));
+    assert_eq!(mmv.value_of("language.users", Some("go")), Some(TypedValue::U64(8388608)));
+    assert_eq!(mmv.value_of("language.users", Some("cobol")), None);
+    assert_eq!(mmv.value_of("language.users", None), None);
+    assert_eq!(mmv.value_of("language.admins", Some("php")), None);
+
+    let mmv = dump(&testdata_path("mmvdump_ip6.mmv")).unwrap();
+    assert_eq!(mmv.value_of("cache_size", Some("L2")), Some(TypedValue::I32(8192)));
+    assert_eq!(mmv.value_of("cpu", None), Some(TypedValue::String("kabylake".to_owned())));
+    assert_eq!(mmv.value_of("cpu", Some("L2")), None);
+}
@@ -0,0 +1,109 @@
+use super::*;
+use super::super::client::metric::Semantics;
+
+// Prometheus metric names must match [a-zA-Z_:][a-zA-Z0-9_:]*
+fn sanitize_name(name: &str) -> String {
+    let mut sanitized: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == ':' { c } else { '_' })
+        .collect();
+    if sanitized.chars().next().map_or(true, |c| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+fn escape(string: &str, escape_quotes: bool) -> String {
+    let mut escaped = String::with_capacity(string.len());
+    for c in string.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '"' if escape_quotes => escaped.push_str("\\\""),
+            _ => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+// Prometheus spells non-finite floats differently from Rust
+fn format_non_finite(val: f64) -> String {
+    if val.is_nan() {
+        "NaN".to_owned()
+    } else if val > 0.0 {
+        "+Inf".to_owned()
+    } else {
+        "-Inf".to_owned()
+    }
+}
+
+fn format_value(val: &TypedValue) -> Option<String> {
+    match *val {
+        TypedValue::F32(val) if !val.is_finite() => Some(format_non_finite(val as f64)),
+        TypedValue::F64(val) if !val.is_finite() => Some(format_non_finite(val)),
+        TypedValue::String(_) => None,
+        ref val => Some(format!("{}", val))
+    }
+}
+
+/// Returns the metrics in a parsed MMV in the Prometheus text
+/// exposition format
+///
+/// Metrics with `Semantics::Counter` are exposed as counters, and all
+/// others as gauges. Characters in metric names that Prometheus doesn't
+/// allow are replaced with `_`, and the values of instance metrics are
+/// exposed as series labeled by `instance`. String metrics are skipped.
+pub fn to_prometheus(mmv: &MMV) -> String {
+    let mut output = String::new();
+
+    for (offset, metric) in mmv.metric_blks() {
+        if metric.item().is_none() {
+            continue;
+        }
+
+        let name = match mmv.resolve_string(metric.name()) {
+            Some(name) => sanitize_name(name),
+            None => continue
+        };
+
+        if metric.typ() == MTCode::String as u32 {
+            output.push_str(&format!("# skipping string metric {}\n", name));
+            continue;
+        }
+
+        if let Some(ref short_help_offset) = *metric.short_help_offset() {
+            if let Some(shorthelp) = mmv.string_blks().get(short_help_offset) {
+                output.push_str(&format!(
+                    "# HELP {} {}\n", name, escape(shorthelp.string(), false)
+                ));
+            }
+        }
+
+        let typ = match Semantics::from_u32(metric.sem()) {
+            Some(Semantics::Counter) => "counter",
+            _ => "gauge"
+        };
+        output.push_str(&format!("# TYPE {} {}\n", name, typ));
+
+        for value in mmv.values_for_metric(*offset) {
+            let val = match mmv.typed_value(metric, value).as_ref().and_then(format_value) {
+                Some(val) => val,
+                None => continue
+            };
+
+            match *value.instance_offset() {
+                Some(ref instance_offset) => {
+                    let instance = mmv.instance_blks().get(instance_offset)
+                        .and_then(|instance| mmv.resolve_string(instance.external_id()));
+                    if let Some(instance) = instance {
+                        output.push_str(&format!(
+                            "{}{{instance=\"{}\"}} {}\n", name, escape(instance, true), val
+                        ));
+                    }
+                },
+                None => output.push_str(&format!("{} {}\n", name, val))
+            }
+        }
+    }
+
+    output
+}
@@ -0,0 +1,21 @@
+extern crate serde_json;
+
+use std::path::PathBuf;
+use std::process::Command;
+
+#[test]
+fn test_mmvdump_json() {
+    let mut input_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    input_path.push("tests/data/mmvdump_ip6.mmv");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_mmvdump"))
+        .arg("--json")
+        .arg(&input_path)
+        .output().unwrap();
+    assert!(output.status.success());
+
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(json["header"].is_object());
+    assert!(!json["metrics"].as_array().unwrap().is_empty());
+    assert!(json["indoms"].is_array());
+}
@@ -10,26 +10,29 @@ use std::path::PathBuf;
 fn test_mmvfmt() {
     let mut testdata_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     testdata_dir.push("tests/data");
-    let tests = fs::read_dir(&testdata_dir).unwrap().count() / 2;
-
     let input_prefix = "mmvdump_ip";
     let input_suffix = ".mmv";
     let output_prefix = "mmvdump_op";
     let output_suffix = ".golden";
 
+    let tests = fs::read_dir(&testdata_dir).unwrap()
+        .filter(|entry|
+            entry.as_ref().unwrap().file_name()
+                .to_string_lossy().starts_with(input_prefix)
+        )
+        .count();
+
     for i in 1..tests+1 {
         let mut output_path = testdata_dir.clone();
         output_path.push(&format!("{}{}{}", output_prefix, i, output_suffix));
-        let mut golden_output = Vec::new();
+        let mut golden_output = String::new();
         File::open(output_path).unwrap()
-            .read_to_end(&mut golden_output).unwrap();
+            .read_to_string(&mut golden_output).unwrap();
 
         let mut input_path = testdata_dir.clone();
         input_path.push(&format!("{}{}{}", input_prefix, i, input_suffix));
-        let mmv = mmv::dump(&input_path).unwrap();
-        let mut mmvdumm_output = Vec::new();
-        write!(&mut mmvdumm_output, "{}", mmv).unwrap();
+        let mmvdump_output = mmv::dump_to_string(&input_path).unwrap();
 
-        assert_eq!(mmvdumm_output, golden_output);
+        assert_eq!(mmvdump_output, golden_output);
     }
 }
@@ -0,0 +1,26 @@
+extern crate hornet;
+
+use hornet::mmv;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+#[test]
+fn test_prometheus() {
+    let mut testdata_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    testdata_dir.push("tests/data");
+
+    for i in &[1, 2, 3, 5, 6] {
+        let mut output_path = testdata_dir.clone();
+        output_path.push(&format!("prometheus_op{}.golden", i));
+        let mut golden_output = String::new();
+        File::open(output_path).unwrap()
+            .read_to_string(&mut golden_output).unwrap();
+
+        let mut input_path = testdata_dir.clone();
+        input_path.push(&format!("mmvdump_ip{}.mmv", i));
+        let mmv = mmv::dump(&input_path).unwrap();
+
+        assert_eq!(mmv::to_prometheus(&mmv), golden_output);
+    }
+}